@@ -0,0 +1,179 @@
+use std::collections::HashSet;
+
+use crate::diff::{FileDiff, LineKind};
+use crate::{Comment, Side};
+
+/// Checks each comment's location against the diff, correcting line numbers that are off by a
+/// few lines and setting [`Comment::anchored`] for the comments that could be located.
+///
+/// The quoted `line` is trusted over the line numbers, since models are much better at
+/// copying code than at counting lines.
+pub fn anchor_comments(comments: &mut [Comment], files: &[FileDiff]) {
+    for comment in comments {
+        let path = comment.path.trim().to_string();
+        // models sometimes copy the `a/` or `b/` prefix from the diff header
+        let unprefixed = ["./", "a/", "b/"]
+            .iter()
+            .find_map(|prefix| path.strip_prefix(prefix))
+            .unwrap_or(&path)
+            .to_string();
+        comment.anchored = false;
+
+        for side in [comment.side, comment.side.other()] {
+            let Some((file, path)) = files.iter().find_map(|file| {
+                let file_path = side_path(file, side)?;
+                (*file_path == path || *file_path == unprefixed).then_some((file, file_path))
+            }) else {
+                continue;
+            };
+
+            if let Some(line) = find_line(file, side, &comment.line, comment.start_line) {
+                let length = comment.end_line.saturating_sub(comment.start_line);
+                comment.path = path.clone();
+                comment.side = side;
+                comment.start_line = line;
+                comment.end_line = end_line(file, side, line, length);
+                comment.anchored = true;
+                break;
+            }
+        }
+    }
+}
+
+fn side_path(file: &FileDiff, side: Side) -> Option<&String> {
+    match side {
+        Side::Old => file.old_path.as_ref(),
+        Side::New => file.new_path.as_ref(),
+    }
+}
+
+/// How long a line must be to match a quote that contains it
+const MIN_QUOTED_PART: usize = 8;
+
+/// The last line of a comment starting at `start_line` and spanning `length` more lines, cut
+/// short at the end of the hunk so that the comment stays within the diff
+fn end_line(file: &FileDiff, side: Side, start_line: u32, length: u32) -> u32 {
+    let numbers: HashSet<u32> = file
+        .lines()
+        .filter_map(|line| match side {
+            Side::Old => line.old_line,
+            Side::New => line.new_line,
+        })
+        .collect();
+
+    let mut end = start_line;
+    while end - start_line < length && numbers.contains(&(end + 1)) {
+        end += 1;
+    }
+    end
+}
+
+/// Finds the line of the file that best matches the quoted code, preferring lines close to
+/// `reported_line`. Without a usable quote, the reported line is accepted if it is in the diff.
+fn find_line(file: &FileDiff, side: Side, quoted: &str, reported_line: u32) -> Option<u32> {
+    let candidates: Vec<(u32, String, bool)> = file
+        .lines()
+        .filter_map(|line| {
+            let number = match side {
+                Side::Old => line.old_line,
+                Side::New => line.new_line,
+            };
+            let changed = line.kind != LineKind::Context;
+            number.map(|n| (n, normalize_code(&line.text), changed))
+        })
+        .collect();
+
+    let quoted = quoted
+        .lines()
+        .map(normalize_code)
+        .find(|line| !line.is_empty());
+
+    let Some(quoted) = quoted else {
+        return candidates
+            .iter()
+            .any(|(n, _, _)| *n == reported_line)
+            .then_some(reported_line);
+    };
+
+    candidates
+        .iter()
+        .filter_map(|(n, text, changed)| {
+            let score = if *text == quoted {
+                2
+            } else if text.contains(&quoted)
+                // a quote can run past its line, but a short line like `}` is in most quotes
+                || (text.len() >= MIN_QUOTED_PART && quoted.contains(text.as_str()))
+            {
+                1
+            } else {
+                return None;
+            };
+            Some((score, *n, *changed))
+        })
+        // prefer the closest of the best matches, and changed lines over identical context lines
+        .max_by_key(|(score, n, changed)| {
+            (
+                *score,
+                std::cmp::Reverse(n.abs_diff(reported_line)),
+                *changed,
+            )
+        })
+        .map(|(_, n, _)| n)
+}
+
+pub fn normalize_code(code: &str) -> String {
+    code.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff;
+
+    const DIFF: &str = "diff --git a/src/f.rs b/src/f.rs\n--- a/src/f.rs\n+++ b/src/f.rs\n@@ -10,5 +10,5 @@\n fn f() {\n     let x = 1;\n-    dbg!(x);\n+    println!(\"{}\", x);\n     x\n }\n";
+
+    fn comment(path: &str, line: u32, quoted: &str) -> Comment {
+        Comment::on(path, line, line).quoting(quoted)
+    }
+
+    fn anchor(mut comment: Comment) -> Comment {
+        anchor_comments(std::slice::from_mut(&mut comment), &diff::parse(DIFF));
+        comment
+    }
+
+    #[test]
+    fn line_numbers_that_are_slightly_off_are_corrected() {
+        let anchored = anchor(comment("src/f.rs", 14, "println!(\"{}\", x);"));
+        assert!(anchored.anchored);
+        assert_eq!((anchored.start_line, anchored.end_line), (12, 12));
+
+        // the length of multi-line comments is kept
+        let anchored = anchor(Comment::on("src/f.rs", 9, 10).quoting("let x = 1;"));
+        assert_eq!((anchored.start_line, anchored.end_line), (11, 12));
+
+        // but not past the end of the hunk
+        let anchored = anchor(Comment::on("src/f.rs", 13, u32::MAX).quoting("x"));
+        assert!(anchored.anchored);
+        assert_eq!((anchored.start_line, anchored.end_line), (13, 14));
+    }
+
+    #[test]
+    fn removed_lines_are_found_on_the_old_side() {
+        let anchored = anchor(comment("b/src/f.rs", 12, "    dbg!(x);"));
+        assert!(anchored.anchored);
+        assert_eq!(anchored.path, "src/f.rs");
+        assert_eq!(anchored.side, Side::Old);
+        assert_eq!(anchored.start_line, 12);
+    }
+
+    #[test]
+    fn comments_that_are_not_in_the_diff_are_unanchored() {
+        assert!(!anchor(comment("src/f.rs", 12, "unrelated()")).anchored);
+        assert!(!anchor(comment("src/g.rs", 12, "let x = 1;")).anchored);
+        // short lines like `}` are not found in longer quotes
+        assert!(!anchor(comment("src/f.rs", 14, "if done { return; }")).anchored);
+        // without a quote, only a line in the diff is accepted
+        assert!(anchor(comment("src/f.rs", 13, "")).anchored);
+        assert!(!anchor(comment("src/f.rs", 30, "")).anchored);
+    }
+}
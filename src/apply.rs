@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+
+use anyhow::Context;
+use b4sam::fix::{apply, by_file, locate, unified_diff, SourceFile};
+use b4sam::Review;
+
+/// Shows the fixes suggested in the review and applies the ones the user picks (all of them
+/// with `yes`) to the working tree, or writes them to `patch`
+pub fn run(review: &Review, yes: bool, dry_run: bool, patch: Option<&Path>) -> anyhow::Result<()> {
+    let root = b4sam::repo_root().context("Not in a git repository")?;
+
+    let mut files: BTreeMap<String, SourceFile> = BTreeMap::new();
+    let mut edits = Vec::new();
+    for comment in review.comments.iter().filter(|c| c.fix.is_some()) {
+        if !files.contains_key(&comment.path) {
+            match SourceFile::read(&root, &comment.path) {
+                Ok(file) => {
+                    files.insert(comment.path.clone(), file);
+                }
+                Err(e) => {
+                    eprintln!("Skipping the fix for {}: {:#}", comment.location(), e);
+                    continue;
+                }
+            }
+        }
+        match locate(comment, &files[&comment.path]) {
+            Ok(edit) => edits.push((comment.path.clone(), edit)),
+            Err(reason) => eprintln!("Skipping the fix for {}: {}", comment.location(), reason),
+        }
+    }
+
+    if edits.is_empty() {
+        println!("No fixes to apply");
+        return Ok(());
+    }
+
+    let mut selected = Vec::new();
+    let mut apply_all = yes;
+    for (path, edit) in edits {
+        println!(
+            "[{}] {}: {}",
+            edit.comment.comment_type,
+            edit.comment.location(),
+            edit.comment.comment
+        );
+        print!("{}", unified_diff(&path, &files[&path], &[&edit]));
+        if dry_run {
+            println!();
+            continue;
+        }
+        if !apply_all {
+            match ask("Apply this fix [y,n,a,q]? ")?.as_str() {
+                "y" => {}
+                "a" => apply_all = true,
+                "q" => break,
+                _ => continue,
+            }
+        }
+        selected.push((path, edit));
+    }
+
+    if dry_run || selected.is_empty() {
+        return Ok(());
+    }
+
+    let (selected, overlapping) = by_file(&selected);
+    for edit in overlapping {
+        eprintln!(
+            "Skipping the fix for {}, which overlaps another fix",
+            edit.comment.location()
+        );
+    }
+    let count: usize = selected.values().map(Vec::len).sum();
+    match patch {
+        Some(patch) => {
+            let contents: String = selected
+                .iter()
+                .map(|(path, edits)| unified_diff(path, &files[*path], edits))
+                .collect();
+            std::fs::write(patch, contents)
+                .with_context(|| format!("Failed to write {}", patch.display()))?;
+            println!("Wrote {} fix(es) to {}", count, patch.display());
+        }
+        None => {
+            for (path, edits) in &selected {
+                std::fs::write(root.join(path), apply(&files[*path], edits))
+                    .with_context(|| format!("Failed to write {}", path))?;
+            }
+            println!("Applied {} fix(es)", count);
+        }
+    }
+
+    Ok(())
+}
+
+fn ask(question: &str) -> anyhow::Result<String> {
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!(
+            "Pass --yes to apply the fixes without asking, or --dry-run to only show them"
+        );
+    }
+
+    print!("{}", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer.trim().to_lowercase())
+}
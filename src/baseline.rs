@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+
+use crate::anchor::normalize_code;
+use crate::{Comment, CommentType};
+
+/// The baseline file, at the root of the repository
+pub const BASELINE_FILE: &str = ".b4sam-baseline.json";
+
+/// Comments that have been looked at and should not be reported again
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+pub struct Baseline {
+    pub entries: Vec<Entry>,
+}
+
+/// A suppressed comment. Only the fingerprint is used for matching; the rest is there so the
+/// file can be reviewed by people.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub fingerprint: String,
+    pub path: String,
+    pub comment_type: CommentType,
+    pub line: String,
+}
+
+/// Identifies a comment by its file, type and quoted line, so that it still matches when the
+/// code around it moves or the model words the comment differently
+pub fn fingerprint(comment: &Comment) -> String {
+    let mut hasher = Sha256::new();
+    for part in [
+        comment.path.as_str(),
+        &comment.comment_type.to_string(),
+        &normalize_code(&comment.line),
+    ] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+impl Baseline {
+    /// The path of the baseline file, or `None` outside a repository
+    pub fn path() -> Option<PathBuf> {
+        crate::repo_root().map(|root| root.join(BASELINE_FILE))
+    }
+
+    /// Reads the baseline, which is empty if there is no baseline file
+    pub fn load() -> anyhow::Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+
+        serde_json::from_str(&contents).with_context(|| format!("Invalid {}", path.display()))
+    }
+
+    pub fn save(&self) -> anyhow::Result<PathBuf> {
+        let path = Self::path().context("Not in a git repository")?;
+        let mut contents = serde_json::to_string_pretty(self)?;
+        contents.push('\n');
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    pub fn contains(&self, comment: &Comment) -> bool {
+        let fingerprint = fingerprint(comment);
+        self.entries
+            .iter()
+            .any(|entry| entry.fingerprint == fingerprint)
+    }
+
+    /// Adds the comment if it is not in the baseline yet, returning whether it was added
+    pub fn add(&mut self, comment: &Comment) -> bool {
+        if self.contains(comment) {
+            return false;
+        }
+
+        self.entries.push(Entry {
+            fingerprint: fingerprint(comment),
+            path: comment.path.clone(),
+            comment_type: comment.comment_type,
+            line: comment.line.trim().to_string(),
+        });
+        // keep the file in a stable order, so that updates make small diffs
+        self.entries.sort_by(|a, b| {
+            (&a.path, &a.line, &a.fingerprint).cmp(&(&b.path, &b.line, &b.fingerprint))
+        });
+        true
+    }
+}
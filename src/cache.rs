@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::Review;
+
+/// Reviews of diff chunks, stored as JSON files under the user's cache directory, so that
+/// reviewing the same chunk again with the same prompt and model doesn't make a request
+pub struct Cache {
+    dir: PathBuf,
+}
+
+/// How much is in the cache
+pub struct Stats {
+    pub dir: PathBuf,
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+impl Cache {
+    /// `$XDG_CACHE_HOME/b4sam/reviews` (or the platform equivalent), or `None` if the platform
+    /// has no cache directory
+    pub fn open() -> Option<Self> {
+        dirs::cache_dir().map(|dir| Cache {
+            dir: dir.join("b4sam").join("reviews"),
+        })
+    }
+
+    /// Identifies a request by everything that goes into it and where it is sent, since the same
+    /// model name can mean different models on different servers
+    pub fn key(system_prompt: &str, config: &Config, diff: &str) -> String {
+        let provider = format!("{:?}", config.provider.unwrap_or_default());
+        let base_url = config.base_url.as_deref().unwrap_or_default();
+        let mut hasher = Sha256::new();
+        for part in [system_prompt, &provider, base_url, config.model(), diff] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.path(key).is_file()
+    }
+
+    /// The cached review, if there is one. Entries that can't be read are treated as missing.
+    pub fn get(&self, key: &str) -> Option<Review> {
+        let contents = std::fs::read_to_string(self.path(key)).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub fn put(&self, key: &str, review: &Review) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+
+        // write to a temporary file first, so a concurrent run never reads half an entry
+        let path = self.path(key);
+        let temp = self.dir.join(format!("{}.{}.tmp", key, std::process::id()));
+        std::fs::write(&temp, serde_json::to_string(review)?)
+            .with_context(|| format!("Failed to write {}", temp.display()))?;
+        std::fs::rename(&temp, &path).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Removes every entry, returning how many there were
+    pub fn clear(&self) -> anyhow::Result<usize> {
+        let entries = self.stats()?.entries;
+        match std::fs::remove_dir_all(&self.dir) {
+            Ok(()) => Ok(entries),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e).with_context(|| format!("Failed to remove {}", self.dir.display())),
+        }
+    }
+
+    pub fn stats(&self) -> anyhow::Result<Stats> {
+        let mut stats = Stats {
+            dir: self.dir.clone(),
+            entries: 0,
+            bytes: 0,
+        };
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(stats),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", self.dir.display()));
+            }
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            if entry.path().extension().is_some_and(|ext| ext == "json") {
+                stats.entries += 1;
+                stats.bytes += entry.metadata()?.len();
+            }
+        }
+        Ok(stats)
+    }
+}
@@ -0,0 +1,284 @@
+use std::process::Command;
+
+use anyhow::Context;
+
+use crate::config::Config;
+use crate::git_output;
+
+/// Where the changes to review come from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffSource {
+    /// Commits between `against` (or the merge-base with the base branch) and `HEAD`
+    Committed { against: Option<String> },
+    /// Changes staged in the index, against `against` (or `HEAD`)
+    Staged { against: Option<String> },
+    /// Changes in the working tree that have not been staged, including untracked files
+    Unstaged,
+    /// Staged, unstaged and untracked changes together, against `against` (or `HEAD`)
+    Worktree { against: Option<String> },
+    /// The changes made by a single commit, along with its message
+    Commit(String),
+}
+
+/// A diff collected for review
+pub struct Changes {
+    /// The revision the diff was taken against, or `None` when diffing the index against the working tree
+    pub base: Option<String>,
+    pub diff: String,
+    /// The commit the diff is from, when reviewing commits one at a time
+    pub commit: Option<Commit>,
+    /// Where the changed files can be read as they are after the changes
+    pub target: Target,
+}
+
+/// A version of the repository's files
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// The files in a commit
+    Revision(String),
+    /// The files staged in the index
+    Index,
+    /// The files in the working tree
+    Worktree,
+}
+
+/// A commit reviewed on its own
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct Commit {
+    pub sha: String,
+    pub subject: String,
+    /// The full commit message
+    #[serde(skip)]
+    pub message: String,
+}
+
+impl DiffSource {
+    /// Runs git to collect the changes, failing if there are none. A single commit's diff may be
+    /// empty instead, e.g. when it only touches excluded files.
+    pub fn changes(&self, config: &Config) -> anyhow::Result<Changes> {
+        let against = match self {
+            DiffSource::Committed { against }
+            | DiffSource::Staged { against }
+            | DiffSource::Worktree { against } => against.as_deref(),
+            DiffSource::Unstaged => None,
+            DiffSource::Commit(sha) => return commit_changes(sha, config),
+        };
+        // Validate the against revision if provided
+        if let Some(rev) = against {
+            let validate = Command::new("git")
+                .args(["rev-parse", "--verify", rev])
+                .output();
+
+            if !matches!(validate, Ok(ref o) if o.status.success()) {
+                anyhow::bail!("Invalid git revision: {}", rev);
+            }
+        }
+
+        let mut args = vec![
+            "diff".to_string(),
+            "--no-color".to_string(),
+            "--src-prefix=a/".to_string(),
+            "--dst-prefix=b/".to_string(),
+            format!("-U{}", config.context_lines()),
+        ];
+        let base = match self {
+            DiffSource::Committed { .. } => {
+                let base = match against {
+                    Some(commit) => commit.to_string(),
+                    None => merge_base(config.base_branch.as_deref())?,
+                };
+                args.extend([base.clone(), "HEAD".to_string()]);
+                Some(base)
+            }
+            DiffSource::Staged { .. } => {
+                let base = against.unwrap_or("HEAD").to_string();
+                args.extend(["--cached".to_string(), base.clone()]);
+                Some(base)
+            }
+            DiffSource::Worktree { .. } => {
+                let base = against.unwrap_or("HEAD").to_string();
+                args.push(base.clone());
+                Some(base)
+            }
+            DiffSource::Unstaged | DiffSource::Commit(_) => None,
+        };
+        let target = match self {
+            DiffSource::Staged { .. } => Target::Index,
+            DiffSource::Unstaged | DiffSource::Worktree { .. } => Target::Worktree,
+            DiffSource::Committed { .. } | DiffSource::Commit(_) => {
+                Target::Revision("HEAD".to_string())
+            }
+        };
+        args.push("--".to_string());
+        args.extend(config.pathspecs());
+
+        let diff_output = Command::new("git")
+            .args(&args)
+            .output()
+            .context("Failed to run `git diff`")?;
+
+        if !diff_output.status.success() {
+            anyhow::bail!("`git diff` failed with status: {}", diff_output.status);
+        }
+
+        let mut diff = String::from_utf8_lossy(&diff_output.stdout).to_string();
+
+        if matches!(self, DiffSource::Unstaged | DiffSource::Worktree { .. }) {
+            diff.push_str(&untracked_changes(config)?);
+        }
+
+        if diff.is_empty() {
+            anyhow::bail!("No changes found");
+        }
+
+        Ok(Changes {
+            base,
+            diff,
+            commit: None,
+            target,
+        })
+    }
+}
+
+/// The commits between the base revision (`against`, or else the merge-base) and `HEAD`, oldest
+/// first. Merge commits are left out, since their changes are in the commits they merge.
+pub fn commits(against: Option<&str>, config: &Config) -> anyhow::Result<Vec<String>> {
+    let base = match against {
+        Some(commit) => commit.to_string(),
+        None => merge_base(config.base_branch.as_deref())?,
+    };
+    let range = format!("{}..HEAD", base);
+    let commits = git_output(&["rev-list", "--reverse", "--no-merges", &range])?;
+    if commits.is_empty() {
+        anyhow::bail!("No commits found in {}", range);
+    }
+
+    Ok(commits.lines().map(str::to_string).collect())
+}
+
+/// The changes made by a single commit, along with its message
+fn commit_changes(sha: &str, config: &Config) -> anyhow::Result<Changes> {
+    let context_lines = format!("-U{}", config.context_lines());
+    let mut args = vec![
+        "show",
+        "--format=",
+        "--no-color",
+        "--src-prefix=a/",
+        "--dst-prefix=b/",
+        &context_lines,
+        sha,
+        "--",
+    ];
+    let pathspecs = config.pathspecs();
+    args.extend(pathspecs.iter().map(String::as_str));
+    let diff = git_output(&args)?;
+
+    let commit = Commit {
+        sha: git_output(&["rev-parse", "--short", sha])?,
+        subject: git_output(&["log", "-1", "--format=%s", sha])?,
+        message: git_output(&["log", "-1", "--format=%B", sha])?,
+    };
+    let parent = format!("{}^", sha);
+    Ok(Changes {
+        // root commits have no parent
+        base: git_output(&["rev-parse", "--verify", "--quiet", &parent]).ok(),
+        diff: format!("{}\n", diff),
+        commit: Some(commit),
+        target: Target::Revision(sha.to_string()),
+    })
+}
+
+fn merge_base(base_branch: Option<&str>) -> anyhow::Result<String> {
+    if let Some(branch) = base_branch {
+        let merge_base_output = Command::new("git")
+            .args(["merge-base", branch, "HEAD"])
+            .output()
+            .context("Failed to run `git merge-base`")?;
+
+        if !merge_base_output.status.success() {
+            anyhow::bail!("Failed to find merge base with {}", branch);
+        }
+
+        return Ok(String::from_utf8_lossy(&merge_base_output.stdout)
+            .trim()
+            .to_string());
+    }
+
+    // Try with origin/main first
+    let mut merge_base_output = Command::new("git")
+        .args(["merge-base", "origin/main", "HEAD"])
+        .output();
+
+    // If that fails, try with origin/master
+    if !matches!(merge_base_output, Ok(ref o) if o.status.success()) {
+        merge_base_output = Command::new("git")
+            .args(["merge-base", "origin/master", "HEAD"])
+            .output();
+    }
+
+    let merge_base_output = merge_base_output.context("Failed to run `git merge-base`")?;
+    let merge_base = String::from_utf8_lossy(&merge_base_output.stdout)
+        .trim()
+        .to_string();
+
+    if merge_base.is_empty() {
+        anyhow::bail!("Failed to find merge base with origin/main or origin/master");
+    }
+
+    Ok(merge_base)
+}
+
+/// Diffs every untracked (and not ignored) file against `/dev/null`, since `git diff` skips them.
+/// Paths are relative to the top level of the repository, like those of `git diff`.
+fn untracked_changes(config: &Config) -> anyhow::Result<String> {
+    let root = crate::repo_root().context("Not in a git repository")?;
+    let ls_output = Command::new("git")
+        .args([
+            "ls-files",
+            "--others",
+            "--exclude-standard",
+            "--full-name",
+            "-z",
+            "--",
+        ])
+        .args(config.pathspecs())
+        .output()
+        .context("Failed to run `git ls-files`")?;
+
+    if !ls_output.status.success() {
+        anyhow::bail!("`git ls-files` failed with status: {}", ls_output.status);
+    }
+
+    let mut changes = String::new();
+    for path in String::from_utf8_lossy(&ls_output.stdout)
+        .split('\0')
+        .filter(|p| !p.is_empty())
+    {
+        let diff_output = Command::new("git")
+            .args([
+                "diff",
+                "--no-index",
+                "--no-color",
+                "--src-prefix=a/",
+                "--dst-prefix=b/",
+                &format!("-U{}", config.context_lines()),
+            ])
+            .args(["--", "/dev/null", path])
+            .current_dir(&root)
+            .output()
+            .context("Failed to run `git diff --no-index`")?;
+
+        // `git diff --no-index` exits with 1 when the files differ, which is always the case here
+        if !matches!(diff_output.status.code(), Some(0 | 1)) {
+            anyhow::bail!(
+                "`git diff --no-index` failed for {} with status: {}",
+                path,
+                diff_output.status
+            );
+        }
+
+        changes.push_str(&String::from_utf8_lossy(&diff_output.stdout));
+    }
+
+    Ok(changes)
+}
@@ -0,0 +1,164 @@
+use crate::diff::FileDiff;
+
+/// A part of the diff small enough to be reviewed in one request
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub diff: String,
+    pub tokens: usize,
+}
+
+/// A rough token count, for budgeting. Code averages a little under four bytes per token.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+/// Cuts text down to about `budget` tokens, for requests that only need the gist of a diff
+pub fn truncate(text: &str, budget: usize) -> String {
+    if estimate_tokens(text) <= budget {
+        return text.to_string();
+    }
+
+    let end = (0..=budget * 4)
+        .rev()
+        .find(|&i| text.is_char_boundary(i))
+        .unwrap_or(0);
+    format!("{}\n[the rest of the diff was cut]\n", &text[..end])
+}
+
+/// Groups the files of a diff into chunks of at most `budget` tokens.
+///
+/// Files are kept together where possible, and otherwise split between hunks (repeating the file
+/// header in each chunk). Returns the chunks and the paths of files that had to be skipped,
+/// because one of their hunks is larger than the budget by itself.
+pub fn split(files: &[FileDiff], budget: usize) -> (Vec<Chunk>, Vec<String>) {
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let mut skipped = Vec::new();
+    let mut current = Chunk::default();
+
+    let mut push = |current: &mut Chunk, text: &str, tokens: usize| {
+        if current.tokens + tokens > budget && current.tokens > 0 {
+            chunks.push(std::mem::take(current));
+        }
+        current.diff.push_str(text);
+        current.tokens += tokens;
+    };
+
+    for file in files {
+        let raw = file.raw();
+        let tokens = estimate_tokens(&raw);
+        if tokens <= budget {
+            push(&mut current, &raw, tokens);
+            continue;
+        }
+
+        // The file is too big for one chunk, so send its hunks in as few parts as possible
+        let header_tokens = estimate_tokens(&file.header);
+        let mut part = file.header.clone();
+        let mut part_tokens = header_tokens;
+        for hunk in &file.hunks {
+            let hunk_tokens = estimate_tokens(&hunk.raw);
+            if header_tokens + hunk_tokens > budget {
+                if !skipped.iter().any(|path| path == file.path()) {
+                    skipped.push(file.path().to_string());
+                }
+                continue;
+            }
+            if part_tokens + hunk_tokens > budget {
+                push(&mut current, &part, part_tokens);
+                part = file.header.clone();
+                part_tokens = header_tokens;
+            }
+            part.push_str(&hunk.raw);
+            part_tokens += hunk_tokens;
+        }
+        if part_tokens > header_tokens {
+            push(&mut current, &part, part_tokens);
+        }
+    }
+
+    if current.tokens > 0 {
+        chunks.push(current);
+    }
+
+    (chunks, skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff;
+
+    /// A file with `hunks` hunks of ten added lines of 40 bytes, so about 100 tokens each
+    fn file(path: &str, hunks: u32) -> String {
+        let mut diff = format!(
+            "diff --git a/{path} b/{path}\n--- a/{path}\n+++ b/{path}\n",
+            path = path
+        );
+        for hunk in 0..hunks {
+            let start = hunk * 100 + 1;
+            diff.push_str(&format!("@@ -{},0 +{},10 @@\n", start, start));
+            for _ in 0..10 {
+                diff.push_str(&format!("+{:<38}\n", path));
+            }
+        }
+        diff
+    }
+
+    #[test]
+    fn small_files_are_kept_together() {
+        let diff = format!("{}{}{}", file("a.rs", 1), file("b.rs", 1), file("c.rs", 1));
+        let files = diff::parse(&diff);
+
+        let (chunks, skipped) = split(&files, 1000);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].diff, diff);
+        assert!(skipped.is_empty());
+
+        // each file is about 115 tokens, so only two fit in a chunk
+        let (chunks, _) = split(&files, 250);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(
+            chunks[0].diff,
+            format!("{}{}", file("a.rs", 1), file("b.rs", 1))
+        );
+        assert_eq!(chunks[1].diff, file("c.rs", 1));
+    }
+
+    #[test]
+    fn large_files_are_split_between_hunks() {
+        let files = diff::parse(&file("big.rs", 5));
+        let header = &files[0].header;
+
+        let (chunks, skipped) = split(&files, 250);
+        assert!(skipped.is_empty());
+        assert_eq!(chunks.len(), 3);
+        for chunk in &chunks {
+            assert!(chunk.diff.starts_with(header.as_str()));
+            assert!(chunk.tokens <= 250);
+        }
+        let hunks: Vec<usize> = chunks
+            .iter()
+            .map(|chunk| diff::parse(&chunk.diff)[0].hunks.len())
+            .collect();
+        assert_eq!(hunks, vec![2, 2, 1]);
+    }
+
+    #[test]
+    fn files_with_a_hunk_over_the_budget_are_skipped() {
+        let diff = format!("{}{}", file("big.rs", 2), file("small.rs", 1));
+        let mut files = diff::parse(&diff);
+        // make one hunk of big.rs too large for any chunk
+        let extra = format!("+{}\n", "x".repeat(400));
+        files[0].hunks[1].raw.push_str(&extra);
+
+        let (chunks, skipped) = split(&files, 150);
+        assert_eq!(skipped, vec!["big.rs".to_string()]);
+        // the hunk that fits is still reviewed
+        let reviewed: Vec<String> = chunks
+            .iter()
+            .flat_map(|chunk| diff::parse(&chunk.diff))
+            .map(|file| format!("{}:{}", file.path(), file.hunks.len()))
+            .collect();
+        assert_eq!(reviewed, vec!["big.rs:1", "small.rs:1"]);
+    }
+}
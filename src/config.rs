@@ -0,0 +1,246 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::cost::OverBudget;
+use crate::messages::MessageRule;
+use crate::reviewer::Provider;
+use crate::{CommentType, Severity};
+
+/// Name of the project configuration file, looked up in the repository root
+pub const PROJECT_CONFIG_FILE: &str = ".b4sam.toml";
+
+const DEFAULT_MODEL: &str = "o3";
+const DEFAULT_CONTEXT_LINES: u32 = 30;
+const DEFAULT_EXCLUDE: [&str; 3] = ["Cargo.lock", "*.json", "*.csv"];
+const DEFAULT_CHUNK_TOKENS: usize = 100_000;
+const DEFAULT_CONCURRENCY: usize = 4;
+const DEFAULT_CONTEXT_TOKENS: usize = 20_000;
+const DEFAULT_MAX_SUBJECT_LENGTH: usize = 72;
+
+/// Settings that can come from `.b4sam.toml`, the user config file or the command line.
+///
+/// Every field is optional so that layers can be merged, with later layers taking precedence.
+#[derive(serde::Deserialize, clap::Args, Default, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// LLM provider to review the changes with [default: openai]
+    #[arg(long, global = true, value_enum)]
+    pub provider: Option<Provider>,
+
+    /// Base URL of the provider's API, e.g. http://localhost:11434/v1 for Ollama or your Azure
+    /// OpenAI endpoint
+    #[arg(long, global = true)]
+    pub base_url: Option<String>,
+
+    /// API version to request from Azure OpenAI [default: 2024-10-21]
+    #[arg(long, global = true)]
+    pub api_version: Option<String>,
+
+    /// Model (or Azure deployment) to review the changes with [default: o3]
+    #[arg(long, global = true)]
+    pub model: Option<String>,
+
+    /// Number of context lines to include around each change [default: 30]
+    #[arg(short = 'U', long, global = true)]
+    pub context_lines: Option<u32>,
+
+    /// Pathspec to exclude from the diff (can be repeated) [default: Cargo.lock, *.json, *.csv]
+    #[arg(long, global = true)]
+    pub exclude: Option<Vec<String>>,
+
+    /// Branch to find the merge-base with [default: origin/main, then origin/master]
+    #[arg(long, global = true)]
+    pub base_branch: Option<String>,
+
+    /// Comment types the model is allowed to leave [default: all]
+    #[arg(long, global = true, value_delimiter = ',', ignore_case = true)]
+    pub comment_types: Option<Vec<CommentType>>,
+
+    /// Extra instructions appended to the system prompt
+    #[arg(long, global = true)]
+    pub extra_prompt: Option<String>,
+
+    /// Maximum (estimated) tokens of system prompt and diff to send in one request; larger diffs
+    /// are split
+    /// [default: 100000]
+    #[arg(long, global = true)]
+    pub chunk_tokens: Option<usize>,
+
+    /// Maximum (estimated) tokens of whole files to send with each request for context, on top of
+    /// the diff; 0 sends only the diff [default: 20000]
+    #[arg(long, global = true)]
+    pub context_tokens: Option<usize>,
+
+    /// Also send the files that define functions and types used in the added lines, within
+    /// `context_tokens`
+    #[arg(long, global = true, num_args = 0, default_missing_value = "true")]
+    pub related_files: Option<bool>,
+
+    /// Maximum number of requests to have in flight at once [default: 4]
+    #[arg(long, global = true)]
+    pub concurrency: Option<usize>,
+
+    /// Exit with status 1 if there are comments at or above this severity
+    #[arg(long, global = true, value_enum)]
+    pub fail_on: Option<Severity>,
+
+    /// Conventions to check commit messages against
+    /// [default: subject-length, imperative-mood, matches-diff]
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub message_rules: Option<Vec<MessageRule>>,
+
+    /// Longest allowed commit subject, for the subject-length rule [default: 72]
+    #[arg(long, global = true)]
+    pub max_subject_length: Option<usize>,
+
+    /// Send every chunk to the model, instead of reusing the review of an identical request
+    #[arg(long, global = true, num_args = 0, default_missing_value = "true")]
+    pub no_cache: Option<bool>,
+
+    /// Most the review may cost, in dollars, by a pre-flight estimate
+    #[arg(long, global = true)]
+    pub max_cost: Option<f64>,
+
+    /// What to do when the estimate is over `max_cost` [default: abort]
+    #[arg(long, global = true, value_enum)]
+    pub over_budget: Option<OverBudget>,
+
+    /// Dollars per million prompt and completion tokens, e.g. `2.5,10`, for models b4sam has no
+    /// price for, such as Azure deployments
+    #[arg(long, global = true, value_parser = parse_price)]
+    pub price: Option<(f64, f64)>,
+}
+
+impl Config {
+    /// Loads the user config file and the project config file, with the project taking precedence
+    pub fn load() -> anyhow::Result<Self> {
+        let mut config = match user_config_path() {
+            Some(path) => Config::read(&path)?,
+            None => Config::default(),
+        };
+
+        if let Some(root) = crate::repo_root() {
+            config = config.merge(Config::read(&root.join(PROJECT_CONFIG_FILE))?);
+        }
+
+        Ok(config)
+    }
+
+    /// Reads a config file, returning an empty config if it does not exist
+    fn read(path: &Path) -> anyhow::Result<Self> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+
+        toml::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Returns `self` with every field that is set in `overrides` replaced
+    pub fn merge(self, overrides: Config) -> Self {
+        Config {
+            provider: overrides.provider.or(self.provider),
+            base_url: overrides.base_url.or(self.base_url),
+            api_version: overrides.api_version.or(self.api_version),
+            model: overrides.model.or(self.model),
+            context_lines: overrides.context_lines.or(self.context_lines),
+            exclude: overrides.exclude.or(self.exclude),
+            base_branch: overrides.base_branch.or(self.base_branch),
+            comment_types: overrides.comment_types.or(self.comment_types),
+            extra_prompt: overrides.extra_prompt.or(self.extra_prompt),
+            chunk_tokens: overrides.chunk_tokens.or(self.chunk_tokens),
+            context_tokens: overrides.context_tokens.or(self.context_tokens),
+            related_files: overrides.related_files.or(self.related_files),
+            concurrency: overrides.concurrency.or(self.concurrency),
+            fail_on: overrides.fail_on.or(self.fail_on),
+            message_rules: overrides.message_rules.or(self.message_rules),
+            max_subject_length: overrides.max_subject_length.or(self.max_subject_length),
+            no_cache: overrides.no_cache.or(self.no_cache),
+            max_cost: overrides.max_cost.or(self.max_cost),
+            over_budget: overrides.over_budget.or(self.over_budget),
+            price: overrides.price.or(self.price),
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or(DEFAULT_MODEL)
+    }
+
+    pub fn context_lines(&self) -> u32 {
+        self.context_lines.unwrap_or(DEFAULT_CONTEXT_LINES)
+    }
+
+    /// The pathspecs passed to `git`, selecting everything except the excluded paths
+    pub fn pathspecs(&self) -> Vec<String> {
+        let exclude = match &self.exclude {
+            Some(exclude) => exclude.iter().map(String::as_str).collect(),
+            None => DEFAULT_EXCLUDE.to_vec(),
+        };
+
+        std::iter::once(".".to_string())
+            .chain(exclude.iter().map(|p| format!(":(exclude){}", p)))
+            .collect()
+    }
+
+    pub fn chunk_tokens(&self) -> usize {
+        self.chunk_tokens.unwrap_or(DEFAULT_CHUNK_TOKENS)
+    }
+
+    pub fn context_tokens(&self) -> usize {
+        self.context_tokens.unwrap_or(DEFAULT_CONTEXT_TOKENS)
+    }
+
+    pub fn related_files(&self) -> bool {
+        self.related_files.unwrap_or(false)
+    }
+
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.unwrap_or(DEFAULT_CONCURRENCY).max(1)
+    }
+
+    pub fn comment_types(&self) -> &[CommentType] {
+        self.comment_types.as_deref().unwrap_or(&CommentType::ALL)
+    }
+
+    pub fn message_rules(&self) -> &[MessageRule] {
+        self.message_rules
+            .as_deref()
+            .unwrap_or(&MessageRule::DEFAULT)
+    }
+
+    pub fn max_subject_length(&self) -> usize {
+        self.max_subject_length
+            .unwrap_or(DEFAULT_MAX_SUBJECT_LENGTH)
+    }
+
+    pub fn over_budget(&self) -> OverBudget {
+        self.over_budget.unwrap_or_default()
+    }
+
+    /// Dollars per million prompt and completion tokens: `price`, or else the model's price from
+    /// the built-in table, if it is there
+    pub fn price(&self) -> Option<(f64, f64)> {
+        self.price.or_else(|| crate::cost::price(self.model()))
+    }
+
+    /// Whether reviews of chunks are cached and reused
+    pub fn cache(&self) -> bool {
+        !self.no_cache.unwrap_or(false)
+    }
+}
+
+/// Parses `--price prompt,completion`
+fn parse_price(price: &str) -> Result<(f64, f64), String> {
+    let parse = |part: &str| part.trim().parse::<f64>().map_err(|e| e.to_string());
+    match price.split_once(',') {
+        Some((prompt, completion)) => Ok((parse(prompt)?, parse(completion)?)),
+        None => Err("expected the prompt and completion prices, like `2.5,10`".to_string()),
+    }
+}
+
+/// `$XDG_CONFIG_HOME/b4sam/config.toml` (or the platform equivalent)
+fn user_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("b4sam").join("config.toml"))
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::chunk::estimate_tokens;
+use crate::config::Config;
+use crate::diff::{self, FileDiff, LineKind};
+use crate::{Changes, Target};
+
+/// Most symbols to look up definitions of for one chunk
+const MAX_SYMBOLS: usize = 50;
+
+/// Words that start a definition in common languages, used to find the files that define symbols
+const DEFINITION_KEYWORDS: [&str; 15] = [
+    "fn",
+    "struct",
+    "enum",
+    "trait",
+    "type",
+    "union",
+    "mod",
+    "macro_rules!",
+    "class",
+    "interface",
+    "def",
+    "func",
+    "function",
+    "const",
+    "static",
+];
+
+/// Explains the `<context>` section to the model; added to the system prompt when it is enabled
+pub const PROMPT: &str = "When the prompt has a <context> section before the <diff>, it holds the full contents of files as they are after the changes: the changed files, and possibly files that define symbols the changes use. Use them to understand the changes (for example, to see how something used in the diff is defined), but only comment on the diff, counting line numbers from its hunk headers.";
+
+/// Adds whole files to the prompt of each chunk, so the model can see more than the hunks
+pub struct ContextBuilder<'a> {
+    target: &'a Target,
+    pathspecs: Vec<String>,
+    /// The top level of the repository, which the paths in the diff are relative to
+    root: Option<PathBuf>,
+    /// The most tokens of files to add to one request
+    budget: usize,
+    /// Whether to also add the files that define symbols used in the added lines
+    related: bool,
+}
+
+impl<'a> ContextBuilder<'a> {
+    pub fn new(changes: &'a Changes, config: &Config) -> Self {
+        ContextBuilder {
+            target: &changes.target,
+            pathspecs: config.pathspecs(),
+            root: crate::repo_root(),
+            budget: config.context_tokens(),
+            related: config.related_files(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.budget > 0
+    }
+
+    /// Reads the files that the prompts of the chunks of `files` can include: each changed file,
+    /// and the files that define symbols its added lines use (if enabled). Done once per review,
+    /// so the prompts can be rebuilt cheaply as files are left out.
+    pub fn gather(&self, files: &[FileDiff]) -> Context {
+        let mut context = Context {
+            budget: self.budget,
+            related: HashMap::new(),
+            contents: HashMap::new(),
+        };
+        if !self.is_enabled() {
+            return context;
+        }
+
+        for file in files {
+            let related = if self.related {
+                self.related_files(std::slice::from_ref(file))
+            } else {
+                Vec::new()
+            };
+            for path in file.new_path.iter().chain(&related) {
+                if !context.contents.contains_key(path) {
+                    let contents = self.read(path);
+                    context.contents.insert(path.clone(), contents);
+                }
+            }
+            context.related.insert(file.path().to_string(), related);
+        }
+        context
+    }
+
+    /// A file as it is after the changes, if it exists and is text
+    fn read(&self, path: &str) -> Option<String> {
+        let bytes = match self.target {
+            Target::Revision(rev) => self.git(&["show", &format!("{}:{}", rev, path)])?,
+            Target::Index => self.git(&["show", &format!(":{}", path)])?,
+            Target::Worktree => std::fs::read(self.root.as_ref()?.join(path)).ok()?,
+        };
+        if bytes.contains(&0) {
+            return None;
+        }
+        String::from_utf8(bytes).ok()
+    }
+
+    /// Runs git in the top level of the repository, so paths and pathspecs are relative to it,
+    /// and returns its stdout as is, or `None` if it fails
+    fn git(&self, args: &[&str]) -> Option<Vec<u8>> {
+        let mut command = Command::new("git");
+        if let Some(root) = &self.root {
+            command.current_dir(root);
+        }
+        let output = command.args(args).output().ok()?;
+        output.status.success().then_some(output.stdout)
+    }
+
+    /// The files that define the symbols used in the added lines, those with the most
+    /// definitions first
+    fn related_files(&self, files: &[FileDiff]) -> Vec<String> {
+        let symbols = symbols(files);
+        if symbols.is_empty() {
+            return Vec::new();
+        }
+
+        let pattern = format!(
+            "({})[[:space:]]+({})",
+            DEFINITION_KEYWORDS.join("|"),
+            symbols.join("|")
+        );
+        let mut args = vec!["grep", "--full-name", "-c", "-I", "-w", "-E"];
+        if *self.target == Target::Index {
+            args.push("--cached");
+        }
+        args.extend(["-e", &pattern]);
+        if let Target::Revision(rev) = self.target {
+            args.push(rev);
+        }
+        args.push("--");
+        args.extend(self.pathspecs.iter().map(String::as_str));
+        // `git grep` fails when nothing matches
+        let Some(output) = self.git(&args) else {
+            return Vec::new();
+        };
+
+        let mut counts: Vec<(String, usize)> = String::from_utf8_lossy(&output)
+            .lines()
+            .filter_map(|line| {
+                let (path, count) = line.rsplit_once(':')?;
+                let path = match self.target {
+                    Target::Revision(rev) => path.strip_prefix(rev.as_str())?.strip_prefix(':')?,
+                    Target::Index | Target::Worktree => path,
+                };
+                Some((path.to_string(), count.parse().ok()?))
+            })
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1));
+        counts.into_iter().map(|(path, _)| path).collect()
+    }
+}
+
+/// The files gathered for the prompts of a review
+pub struct Context {
+    /// The most tokens of files to add to one request
+    budget: usize,
+    /// The files that define symbols used in each changed file, those with the most definitions
+    /// first
+    related: HashMap<String, Vec<String>>,
+    /// Each file as it is after the changes, or `None` if it doesn't exist or isn't text
+    contents: HashMap<String, Option<String>>,
+}
+
+impl Context {
+    /// The prompt for a chunk of the diff: the files it changes in full, then the files that
+    /// define symbols its added lines use, as many as fit in the budget, and then the chunk itself
+    pub fn prompt(&self, diff: &str) -> String {
+        if self.budget == 0 {
+            return diff.to_string();
+        }
+
+        let files = diff::parse(diff);
+        let mut paths: Vec<(&str, &str)> = files
+            .iter()
+            .filter_map(|file| Some((file.new_path.as_deref()?, "changed")))
+            .collect();
+        let related = files
+            .iter()
+            .filter_map(|file| self.related.get(file.path()))
+            .flatten();
+        for path in related {
+            if !paths.iter().any(|(included, _)| included == path) {
+                paths.push((path, "related"));
+            }
+        }
+
+        let mut remaining = self.budget;
+        let mut context = String::new();
+        for (path, reason) in paths {
+            let Some(Some(contents)) = self.contents.get(path) else {
+                continue;
+            };
+            let newline = if contents.ends_with('\n') { "" } else { "\n" };
+            let section = format!(
+                "<file path=\"{}\" reason=\"{}\">\n{}{}</file>\n",
+                path, reason, contents, newline
+            );
+            // smaller files further down the list may still fit
+            let tokens = estimate_tokens(&section);
+            if tokens <= remaining {
+                remaining -= tokens;
+                context.push_str(&section);
+            }
+        }
+
+        if context.is_empty() {
+            return diff.to_string();
+        }
+        format!(
+            "<context>\n{}</context>\n\n<diff>\n{}</diff>\n",
+            context, diff
+        )
+    }
+}
+
+/// The names in the added lines that are probably defined elsewhere: the functions they call and
+/// the (capitalized) types they use, in order of appearance
+fn symbols(files: &[FileDiff]) -> Vec<String> {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut symbols: Vec<String> = Vec::new();
+    let added = files
+        .iter()
+        .flat_map(FileDiff::lines)
+        .filter(|line| line.kind == LineKind::Added);
+    for line in added {
+        let mut rest = line.text.as_str();
+        while let Some(start) = rest.find(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            rest = &rest[start..];
+            let (word, after) = rest.split_at(rest.find(|c| !is_word(c)).unwrap_or(rest.len()));
+            rest = after;
+
+            let is_call = after.trim_start().starts_with('(');
+            let is_type = word.starts_with(|c: char| c.is_ascii_uppercase())
+                && word.contains(|c: char| c.is_ascii_lowercase());
+            if (is_call || is_type)
+                && word.len() > 2
+                && !DEFINITION_KEYWORDS.contains(&word)
+                && !symbols.iter().any(|symbol| symbol == word)
+            {
+                symbols.push(word.to_string());
+            }
+        }
+    }
+    symbols.truncate(MAX_SYMBOLS);
+    symbols
+}
@@ -0,0 +1,171 @@
+use tysm::chat_completions::ChatUsage;
+
+use crate::chunk::estimate_tokens;
+
+/// Prices in dollars per million prompt and completion tokens, by model name prefix. The longest
+/// matching prefix wins, so `gpt-4o-mini` isn't priced as `gpt-4o`.
+const PRICES: [(&str, f64, f64); 21] = [
+    ("o1", 15.0, 60.0),
+    ("o1-mini", 1.1, 4.4),
+    ("o3", 2.0, 8.0),
+    ("o3-mini", 1.1, 4.4),
+    ("o3-pro", 20.0, 80.0),
+    ("o4-mini", 1.1, 4.4),
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4.1", 2.0, 8.0),
+    ("gpt-4.1-mini", 0.4, 1.6),
+    ("gpt-4.1-nano", 0.1, 0.4),
+    ("gpt-5", 1.25, 10.0),
+    ("gpt-5-mini", 0.25, 2.0),
+    ("gpt-5-nano", 0.05, 0.4),
+    ("claude-opus-4", 15.0, 75.0),
+    ("claude-sonnet-4", 3.0, 15.0),
+    ("claude-3-7-sonnet", 3.0, 15.0),
+    ("claude-3-5-sonnet", 3.0, 15.0),
+    ("claude-3-5-haiku", 0.8, 4.0),
+    ("claude-haiku-4", 1.0, 5.0),
+    ("claude-3-haiku", 0.25, 1.25),
+];
+
+/// Completion tokens assumed per request. Reviews are short, but reasoning models spend tokens
+/// thinking before they answer.
+const COMPLETION_TOKENS_PER_REQUEST: usize = 4_000;
+
+/// What to do when a review is estimated to cost more than `max_cost`
+#[derive(serde::Deserialize, clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OverBudget {
+    /// Stop without sending anything
+    #[default]
+    Abort,
+    /// Leave out the least important files (docs, then tests, then the largest files) until the
+    /// review fits
+    Trim,
+}
+
+/// The projected size and cost of a review
+#[derive(Debug, Default)]
+pub struct Estimate {
+    pub requests: usize,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    /// The cost in dollars, if the model's prices are known
+    pub cost: Option<f64>,
+}
+
+impl Estimate {
+    /// Estimates the cost of sending each of `prompts` with the system prompt, at `price` dollars
+    /// per million prompt and completion tokens
+    pub fn new<'a>(
+        price: Option<(f64, f64)>,
+        system_prompt: &str,
+        prompts: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        let mut estimate = Estimate::default();
+        for prompt in prompts {
+            estimate.requests += 1;
+            estimate.prompt_tokens += estimate_tokens(system_prompt) + estimate_tokens(prompt);
+            estimate.completion_tokens += COMPLETION_TOKENS_PER_REQUEST;
+        }
+
+        estimate.cost = price.map(|(input, output)| {
+            (estimate.prompt_tokens as f64 * input + estimate.completion_tokens as f64 * output)
+                / 1_000_000.0
+        });
+        estimate
+    }
+}
+
+/// The prices of a model in dollars per million prompt and completion tokens, if known
+pub fn price(model: &str) -> Option<(f64, f64)> {
+    // providers add dates and versions to model names, e.g. `gpt-4o-2024-08-06`
+    let model = model.to_lowercase();
+    PRICES
+        .iter()
+        .filter(|(prefix, _, _)| {
+            model.strip_prefix(prefix).is_some_and(|rest| {
+                rest.is_empty() || rest.starts_with('-') || rest.starts_with('@')
+            })
+        })
+        .max_by_key(|(prefix, _, _)| prefix.len())
+        .map(|&(_, input, output)| (input, output))
+}
+
+/// The cost in dollars of the tokens used at `price`, if the prices are known
+pub fn usage_cost(price: Option<(f64, f64)>, usage: ChatUsage) -> Option<f64> {
+    price.map(|(input, output)| {
+        (usage.prompt_tokens as f64 * input + usage.completion_tokens as f64 * output) / 1_000_000.0
+    })
+}
+
+/// How much a file matters to the review, for deciding what to leave out first: documentation,
+/// then tests, then everything else
+pub fn priority(path: &str) -> u8 {
+    let path = path.to_lowercase();
+    let name = path.rsplit('/').next().unwrap_or(&path);
+    let in_dir =
+        |dir: &str| path.starts_with(&format!("{}/", dir)) || path.contains(&format!("/{}/", dir));
+
+    if [".md", ".rst", ".txt", ".adoc"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+        || in_dir("docs")
+        || in_dir("doc")
+    {
+        0
+    } else if in_dir("tests")
+        || in_dir("test")
+        || in_dir("__tests__")
+        || name.starts_with("test_")
+        || ["_test.", ".test.", ".spec.", "_spec."]
+            .iter()
+            .any(|part| name.contains(part))
+    {
+        1
+    } else {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_longest_matching_prefix_sets_the_price() {
+        assert_eq!(price("gpt-4o"), Some((2.5, 10.0)));
+        assert_eq!(price("gpt-4o-mini"), Some((0.15, 0.6)));
+        assert_eq!(price("GPT-4o-Mini"), Some((0.15, 0.6)));
+        assert_eq!(price("o3-mini"), Some((1.1, 4.4)));
+        // a prefix only matches whole parts of the name
+        assert_eq!(price("o3"), Some((2.0, 8.0)));
+        assert_eq!(price("o30"), None);
+    }
+
+    #[test]
+    fn dated_models_are_priced() {
+        assert_eq!(price("gpt-4o-2024-08-06"), Some((2.5, 10.0)));
+        assert_eq!(price("gpt-4o-mini-2024-07-18"), Some((0.15, 0.6)));
+        assert_eq!(price("claude-3-5-sonnet-20241022"), Some((3.0, 15.0)));
+        assert_eq!(price("claude-3-5-sonnet@20241022"), Some((3.0, 15.0)));
+    }
+
+    #[test]
+    fn unknown_models_have_no_price() {
+        assert_eq!(price("llama3"), None);
+        assert_eq!(price(""), None);
+        assert_eq!(Estimate::new(None, "system", ["prompt"]).cost, None);
+    }
+
+    #[test]
+    fn usage_is_priced_per_million_tokens() {
+        let usage = ChatUsage {
+            prompt_tokens: 1_000_000,
+            completion_tokens: 500_000,
+            ..ChatUsage::default()
+        };
+        assert_eq!(usage_cost(Some((2.0, 8.0)), usage), Some(6.0));
+        assert_eq!(usage_cost(None, usage), None);
+    }
+}
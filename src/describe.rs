@@ -0,0 +1,185 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::chunk::truncate;
+use crate::config::Config;
+use crate::reviewer::Reviewer;
+use crate::{git_output, Changes};
+
+/// Where GitHub looks for a pull request template, relative to the repository root
+const TEMPLATE_PATHS: [&str; 4] = [
+    ".github/pull_request_template.md",
+    ".github/PULL_REQUEST_TEMPLATE.md",
+    "PULL_REQUEST_TEMPLATE.md",
+    "docs/pull_request_template.md",
+];
+
+const DESCRIBE_PROMPT: &str = "You write pull request descriptions. You are given the diff of a branch and the subjects of its commits. Describe what the changes do and why, for a reviewer who has not seen them. Be concise and specific, and don't invent motivation or testing that the changes don't show.";
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+struct Description {
+    /// A short title for the pull request, in the imperative mood
+    title: String,
+    /// A paragraph summarizing what the changes do and why
+    summary: String,
+    /// The notable changes, one per item
+    changes: Vec<String>,
+    /// Parts of the changes that could break something or deserve a careful review, and why
+    risks: Vec<String>,
+    /// Steps a reviewer could take to check that the changes work
+    test_plan: Vec<String>,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+struct FilledTemplate {
+    /// A short title for the pull request, in the imperative mood
+    title: String,
+    /// The content of each section of the template, in order
+    sections: Vec<FilledSection>,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+struct FilledSection {
+    /// The section's heading, copied exactly from the template
+    heading: String,
+    /// The markdown to put under the heading
+    content: String,
+}
+
+/// A heading of a markdown template and the text under it
+struct Section {
+    /// The heading line, or `None` for any text before the first heading
+    heading: Option<String>,
+    body: String,
+}
+
+/// Finds the repository's pull request template
+pub fn find_template() -> Option<PathBuf> {
+    let root = crate::repo_root()?;
+    TEMPLATE_PATHS
+        .iter()
+        .map(|path| root.join(path))
+        .find(|path| path.is_file())
+}
+
+/// Writes a pull request description for the changes and the commits since their base as
+/// markdown, filling in `template` section by section if given
+pub async fn describe(
+    reviewer: &dyn Reviewer,
+    custom_prompt: Option<String>,
+    changes: &Changes,
+    template: Option<&Path>,
+    config: &Config,
+) -> anyhow::Result<String> {
+    let base = changes
+        .base
+        .as_deref()
+        .context("Only changes with a base can be described")?;
+    let subjects = git_output(&[
+        "log",
+        "--reverse",
+        "--format=- %s",
+        &format!("{}..HEAD", base),
+    ])?;
+
+    let mut system_prompt = custom_prompt.unwrap_or_else(|| DESCRIBE_PROMPT.to_string());
+    if let Some(extra_prompt) = &config.extra_prompt {
+        system_prompt.push_str("\n\n");
+        system_prompt.push_str(extra_prompt);
+    }
+    let mut prompt = format!(
+        "Commits:\n\n{}\n\nDiff:\n\n{}",
+        subjects,
+        truncate(&changes.diff, config.chunk_tokens())
+    );
+
+    let Some(template) = template else {
+        let description: Description = reviewer.chat(&system_prompt, &prompt).await?;
+        return Ok(format_description(&description));
+    };
+
+    let contents = std::fs::read_to_string(template)
+        .with_context(|| format!("Failed to read {}", template.display()))?;
+    let sections = parse_sections(&contents);
+    prompt.push_str(&format!(
+        "\n\nFill in this pull request template. For each heading, write the content that should replace the text under it, following any instructions in the template (such as HTML comments). Use an empty heading for any text before the first heading.\n\n{}",
+        contents
+    ));
+    let filled: FilledTemplate = reviewer.chat(&system_prompt, &prompt).await?;
+
+    let mut markdown = format!("# {}\n\n", filled.title.trim());
+    for section in sections {
+        let heading = section.heading.as_deref().unwrap_or_default();
+        let content = filled
+            .sections
+            .iter()
+            .find(|filled| heading_text(&filled.heading) == heading_text(heading))
+            .map(|filled| filled.content.trim())
+            // keep the template's text for sections the model left out
+            .unwrap_or(section.body.trim());
+        if !heading.is_empty() {
+            markdown.push_str(heading);
+            markdown.push_str("\n\n");
+        }
+        if !content.is_empty() {
+            markdown.push_str(content);
+            markdown.push_str("\n\n");
+        }
+    }
+
+    Ok(format!("{}\n", markdown.trim_end()))
+}
+
+fn format_description(description: &Description) -> String {
+    let list = |items: &[String]| -> String {
+        items.iter().fold(String::new(), |mut list, item| {
+            list.push_str("- ");
+            list.push_str(item.trim());
+            list.push('\n');
+            list
+        })
+    };
+
+    let mut markdown = format!(
+        "# {}\n\n{}\n\n## Changes\n\n{}",
+        description.title.trim(),
+        description.summary.trim(),
+        list(&description.changes)
+    );
+    if !description.risks.is_empty() {
+        markdown.push_str(&format!("\n## Risks\n\n{}", list(&description.risks)));
+    }
+    if !description.test_plan.is_empty() {
+        markdown.push_str(&format!(
+            "\n## Test plan\n\n{}",
+            list(&description.test_plan)
+        ));
+    }
+    markdown
+}
+
+/// The text of a heading, without the `#`s, since the model may leave them out
+fn heading_text(heading: &str) -> &str {
+    heading.trim().trim_start_matches('#').trim()
+}
+
+/// Splits a markdown template at its headings
+fn parse_sections(template: &str) -> Vec<Section> {
+    let mut sections = vec![Section {
+        heading: None,
+        body: String::new(),
+    }];
+    for line in template.lines() {
+        if line.starts_with('#') {
+            sections.push(Section {
+                heading: Some(line.trim_end().to_string()),
+                body: String::new(),
+            });
+        } else if let Some(section) = sections.last_mut() {
+            section.body.push_str(line);
+            section.body.push('\n');
+        }
+    }
+    sections
+}
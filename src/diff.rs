@@ -0,0 +1,413 @@
+use std::collections::BTreeSet;
+
+use crate::{Comment, Side};
+
+/// How many unchanged lines GitHub and GitLab show around each change in a pull request's diff,
+/// which limits where comments can be placed
+pub const FORGE_CONTEXT_LINES: usize = 3;
+
+/// The changes to a single file
+#[derive(Debug, Clone)]
+pub struct FileDiff {
+    /// The path before the change, or `None` if the file was added
+    pub old_path: Option<String>,
+    /// The path after the change, or `None` if the file was deleted
+    pub new_path: Option<String>,
+    /// The `diff --git` line and extended header lines, verbatim
+    pub header: String,
+    pub hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    /// The hunk, including its `@@` line, verbatim
+    pub raw: String,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiffLine {
+    pub kind: LineKind,
+    /// The line number in the old file, for context and removed lines
+    pub old_line: Option<u32>,
+    /// The line number in the new file, for context and added lines
+    pub new_line: Option<u32>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    Context,
+    Added,
+    Removed,
+}
+
+impl FileDiff {
+    /// The path the file is best known by: the new path, or the old one if it was deleted
+    pub fn path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// The section of the diff for this file, verbatim
+    pub fn raw(&self) -> String {
+        let mut raw = self.header.clone();
+        for hunk in &self.hunks {
+            raw.push_str(&hunk.raw);
+        }
+        raw
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &DiffLine> {
+        self.hunks.iter().flat_map(|hunk| hunk.lines.iter())
+    }
+
+    /// Where a line of the old file is in the new file, or `None` if it was removed
+    pub fn new_line(&self, old_line: u32) -> Option<u32> {
+        // how many lines were added, less how many were removed, above `old_line`
+        let mut offset: i64 = 0;
+        for hunk in &self.hunks {
+            let (old_start, _) = parse_hunk_header(hunk.raw.lines().next()?)?;
+            let old_len = hunk.lines.iter().filter(|l| l.old_line.is_some()).count() as u32;
+            let new_len = hunk.lines.iter().filter(|l| l.new_line.is_some()).count() as u32;
+
+            // a hunk that only adds lines starts at the line it inserts after
+            let before = if old_len == 0 {
+                old_start < old_line
+            } else {
+                old_start + old_len <= old_line
+            };
+            if before {
+                offset += new_len as i64 - old_len as i64;
+                continue;
+            }
+            if old_len == 0 || old_start > old_line {
+                break;
+            }
+            return hunk
+                .lines
+                .iter()
+                .find(|l| l.old_line == Some(old_line))
+                .and_then(|l| l.new_line);
+        }
+
+        u32::try_from(old_line as i64 + offset).ok()
+    }
+
+    /// The lines of one side of the file that a diff with `context` unchanged lines around each
+    /// change would show
+    pub fn visible_lines(&self, side: Side, context: usize) -> BTreeSet<u32> {
+        let mut visible = BTreeSet::new();
+        for hunk in &self.hunks {
+            // how many unchanged lines each line is from the nearest change above and below it
+            let distances = |lines: &mut dyn Iterator<Item = &DiffLine>| -> Vec<Option<usize>> {
+                let mut distance = None;
+                lines
+                    .map(|line| {
+                        distance = match line.kind {
+                            LineKind::Context => distance.map(|d: usize| d + 1),
+                            LineKind::Added | LineKind::Removed => Some(0),
+                        };
+                        distance
+                    })
+                    .collect()
+            };
+            let above = distances(&mut hunk.lines.iter());
+            let mut below = distances(&mut hunk.lines.iter().rev());
+            below.reverse();
+
+            for (i, line) in hunk.lines.iter().enumerate() {
+                let near = [above[i], below[i]]
+                    .into_iter()
+                    .flatten()
+                    .any(|distance| distance <= context);
+                let number = match side {
+                    Side::Old => line.old_line,
+                    Side::New => line.new_line,
+                };
+                if let (true, Some(number)) = (near, number) {
+                    visible.insert(number);
+                }
+            }
+        }
+        visible
+    }
+}
+
+/// The file a comment is on, if every line it covers is shown in a pull request's diff on GitHub
+/// or GitLab, so that it can be posted there inline
+pub fn postable<'a>(comment: &Comment, files: &'a [FileDiff]) -> Option<&'a FileDiff> {
+    if !comment.anchored {
+        return None;
+    }
+
+    let file = files.iter().find(|file| {
+        let path = match comment.side {
+            Side::Old => &file.old_path,
+            Side::New => &file.new_path,
+        };
+        path.as_deref() == Some(comment.path.as_str())
+    })?;
+    let visible = file.visible_lines(comment.side, FORGE_CONTEXT_LINES);
+    (comment.start_line..=comment.end_line.max(comment.start_line))
+        .all(|line| visible.contains(&line))
+        .then_some(file)
+}
+
+/// Splits a diff into the changes to each file
+pub fn parse(diff: &str) -> Vec<FileDiff> {
+    let mut files: Vec<FileDiff> = Vec::new();
+    // the next line numbers in the old and new file of the hunk being parsed
+    let mut old_line = 0;
+    let mut new_line = 0;
+
+    for line in diff.split_inclusive('\n') {
+        if let Some(header) = line.strip_prefix("diff --git ") {
+            let (old_path, new_path) = parse_git_header(header.trim_end());
+            files.push(FileDiff {
+                old_path,
+                new_path,
+                header: String::new(),
+                hunks: Vec::new(),
+            });
+        }
+
+        let Some(file) = files.last_mut() else {
+            continue;
+        };
+        let content = line.strip_suffix('\n').unwrap_or(line);
+
+        if content.starts_with("@@") {
+            if let Some((old_start, new_start)) = parse_hunk_header(content) {
+                old_line = old_start;
+                new_line = new_start;
+                file.hunks.push(Hunk {
+                    raw: line.to_string(),
+                    lines: Vec::new(),
+                });
+                continue;
+            }
+        }
+
+        let Some(hunk) = file.hunks.last_mut() else {
+            // still in the extended header
+            file.header.push_str(line);
+            if let Some(path) = content.strip_prefix("--- ") {
+                file.old_path = parse_path(path, "a/");
+            } else if let Some(path) = content.strip_prefix("+++ ") {
+                file.new_path = parse_path(path, "b/");
+            } else if let Some(path) = content.strip_prefix("rename from ") {
+                file.old_path = Some(unquote(path));
+            } else if let Some(path) = content.strip_prefix("rename to ") {
+                file.new_path = Some(unquote(path));
+            } else if content.starts_with("new file mode") {
+                file.old_path = None;
+            } else if content.starts_with("deleted file mode") {
+                file.new_path = None;
+            }
+            continue;
+        };
+        hunk.raw.push_str(line);
+
+        let (kind, text) = match content.chars().next() {
+            Some('+') => (LineKind::Added, &content[1..]),
+            Some('-') => (LineKind::Removed, &content[1..]),
+            Some(' ') => (LineKind::Context, &content[1..]),
+            // an empty context line whose leading space was stripped
+            None => (LineKind::Context, ""),
+            // e.g. "\ No newline at end of file"
+            _ => continue,
+        };
+        let (old, new) = match kind {
+            LineKind::Context => (Some(old_line), Some(new_line)),
+            LineKind::Added => (None, Some(new_line)),
+            LineKind::Removed => (Some(old_line), None),
+        };
+        if old.is_some() {
+            old_line += 1;
+        }
+        if new.is_some() {
+            new_line += 1;
+        }
+        hunk.lines.push(DiffLine {
+            kind,
+            old_line: old,
+            new_line: new,
+            text: text.to_string(),
+        });
+    }
+
+    files
+}
+
+/// Parses `a/old b/new`, which is only unambiguous when the paths are the same length
+fn parse_git_header(header: &str) -> (Option<String>, Option<String>) {
+    let header = header.trim();
+    let half = header.len() / 2;
+    if header.is_char_boundary(half) && header.as_bytes().get(half) == Some(&b' ') {
+        let (old, new) = (&header[..half], &header[half + 1..]);
+        return (parse_path(old, "a/"), parse_path(new, "b/"));
+    }
+
+    (None, None)
+}
+
+fn parse_path(path: &str, prefix: &str) -> Option<String> {
+    let path = unquote(path.trim_end_matches('\t'));
+    if path == "/dev/null" {
+        return None;
+    }
+
+    Some(path.strip_prefix(prefix).unwrap_or(&path).to_string())
+}
+
+fn unquote(path: &str) -> String {
+    path.strip_prefix('"')
+        .and_then(|p| p.strip_suffix('"'))
+        .unwrap_or(path)
+        .to_string()
+}
+
+/// Parses `@@ -old_start,old_len +new_start,new_len @@`, returning the start lines
+fn parse_hunk_header(header: &str) -> Option<(u32, u32)> {
+    let mut parts = header.split_whitespace().skip(1);
+    let old = parts.next()?.strip_prefix('-')?;
+    let new = parts.next()?.strip_prefix('+')?;
+    let start = |range: &str| range.split(',').next()?.parse().ok();
+
+    Some((start(old)?, start(new)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visible_lines_are_the_changes_and_three_lines_around_them() {
+        let mut diff = String::from(
+            "diff --git a/f.txt b/f.txt\n--- a/f.txt\n+++ b/f.txt\n@@ -1,12 +1,12 @@\n",
+        );
+        for line in 1..=12 {
+            if line == 6 {
+                diff.push_str("-six\n+SIX\n");
+            } else {
+                diff.push_str(&format!(" {}\n", line));
+            }
+        }
+        let file = &parse(&diff)[0];
+
+        let visible: Vec<u32> = file.visible_lines(Side::New, 3).into_iter().collect();
+        assert_eq!(visible, vec![3, 4, 5, 6, 7, 8, 9]);
+        let visible: Vec<u32> = file.visible_lines(Side::Old, 0).into_iter().collect();
+        assert_eq!(visible, vec![6]);
+    }
+
+    #[test]
+    fn comments_are_postable_only_if_every_line_is_visible() {
+        let diff = "diff --git a/f.txt b/f.txt\n--- a/f.txt\n+++ b/f.txt\n@@ -1,6 +1,6 @@\n 1\n 2\n 3\n 4\n 5\n-6\n+six\n";
+        let files = parse(diff);
+        let comment = |start_line, end_line| Comment::on("f.txt", start_line, end_line);
+
+        assert!(postable(&comment(6, 6), &files).is_some());
+        assert!(postable(&comment(3, 6), &files).is_some());
+        assert!(postable(&comment(1, 6), &files).is_none());
+        assert!(postable(&comment(2, 2), &files).is_none());
+    }
+
+    #[test]
+    fn hunk_headers_set_the_line_numbers() {
+        assert_eq!(
+            parse_hunk_header("@@ -10,3 +12,4 @@ fn main() {"),
+            Some((10, 12))
+        );
+        assert_eq!(parse_hunk_header("@@ -1 +1 @@"), Some((1, 1)));
+        assert_eq!(parse_hunk_header("@@ garbage"), None);
+
+        let diff = "diff --git a/f.rs b/f.rs\n--- a/f.rs\n+++ b/f.rs\n@@ -10,3 +12,4 @@ fn main() {\n a\n-b\n+B\n+C\n c\n";
+        let files = parse(diff);
+        assert_eq!(files.len(), 1);
+        let numbers: Vec<(LineKind, Option<u32>, Option<u32>)> = files[0]
+            .lines()
+            .map(|line| (line.kind, line.old_line, line.new_line))
+            .collect();
+        assert_eq!(
+            numbers,
+            vec![
+                (LineKind::Context, Some(10), Some(12)),
+                (LineKind::Removed, Some(11), None),
+                (LineKind::Added, None, Some(13)),
+                (LineKind::Added, None, Some(14)),
+                (LineKind::Context, Some(12), Some(15)),
+            ]
+        );
+        assert_eq!(files[0].raw(), diff);
+    }
+
+    #[test]
+    fn no_newline_markers_are_not_lines() {
+        let diff = "diff --git a/f.txt b/f.txt\n--- a/f.txt\n+++ b/f.txt\n@@ -1 +1 @@\n-old\n\\ No newline at end of file\n+new\n\\ No newline at end of file\n";
+        let file = &parse(diff)[0];
+        let texts: Vec<&str> = file.lines().map(|line| line.text.as_str()).collect();
+        assert_eq!(texts, vec!["old", "new"]);
+        assert_eq!(file.lines().last().unwrap().new_line, Some(1));
+        // the marker is kept in the raw hunk, so it is still sent to the model
+        assert_eq!(file.raw(), diff);
+    }
+
+    #[test]
+    fn renames_have_both_paths() {
+        let diff = "diff --git a/old name.rs b/new.rs\nsimilarity index 90%\nrename from old name.rs\nrename to new.rs\n--- a/old name.rs\n+++ b/new.rs\n@@ -1 +1 @@\n-a\n+b\n";
+        let file = &parse(diff)[0];
+        assert_eq!(file.old_path.as_deref(), Some("old name.rs"));
+        assert_eq!(file.new_path.as_deref(), Some("new.rs"));
+        assert_eq!(file.path(), "new.rs");
+
+        // a pure rename has no hunks, so the paths come from the rename lines alone
+        let diff = "diff --git a/a.rs b/b/c.rs\nsimilarity index 100%\nrename from a.rs\nrename to b/c.rs\n";
+        let file = &parse(diff)[0];
+        assert_eq!(file.old_path.as_deref(), Some("a.rs"));
+        assert_eq!(file.new_path.as_deref(), Some("b/c.rs"));
+        assert!(file.hunks.is_empty());
+    }
+
+    #[test]
+    fn new_and_deleted_files_have_one_path() {
+        let diff = "diff --git a/new.rs b/new.rs\nnew file mode 100644\nindex 0000000..1111111\n--- /dev/null\n+++ b/new.rs\n@@ -0,0 +1,2 @@\n+a\n+b\ndiff --git a/gone.rs b/gone.rs\ndeleted file mode 100644\nindex 1111111..0000000\n--- a/gone.rs\n+++ /dev/null\n@@ -1 +0,0 @@\n-a\n";
+        let files = parse(diff);
+        assert_eq!(files.len(), 2);
+
+        assert_eq!(files[0].old_path, None);
+        assert_eq!(files[0].new_path.as_deref(), Some("new.rs"));
+        let new_lines: Vec<Option<u32>> = files[0].lines().map(|l| l.new_line).collect();
+        assert_eq!(new_lines, vec![Some(1), Some(2)]);
+
+        assert_eq!(files[1].old_path.as_deref(), Some("gone.rs"));
+        assert_eq!(files[1].new_path, None);
+        assert_eq!(files[1].path(), "gone.rs");
+        assert_eq!(files[1].lines().next().unwrap().old_line, Some(1));
+    }
+
+    #[test]
+    fn new_line_follows_lines_past_the_hunks() {
+        // six becomes two lines, a line is inserted after 10, and 16 is removed
+        let diff = "diff --git a/f.txt b/f.txt\n--- a/f.txt\n+++ b/f.txt\n@@ -5,3 +5,4 @@\n 5\n-6\n+six\n+six again\n 7\n@@ -10,0 +12 @@\n+new\n@@ -15,2 +17 @@\n 15\n-16\n";
+        let file = &parse(diff)[0];
+
+        // above every hunk
+        assert_eq!(file.new_line(1), Some(1));
+        // inside hunks
+        assert_eq!(file.new_line(5), Some(5));
+        assert_eq!(file.new_line(6), None);
+        assert_eq!(file.new_line(7), Some(8));
+        assert_eq!(file.new_line(15), Some(17));
+        assert_eq!(file.new_line(16), None);
+        // between hunks, around the inserted line
+        assert_eq!(file.new_line(8), Some(9));
+        assert_eq!(file.new_line(10), Some(11));
+        assert_eq!(file.new_line(11), Some(13));
+        // below every hunk
+        assert_eq!(file.new_line(20), Some(21));
+    }
+}
@@ -0,0 +1,171 @@
+use clap::ValueEnum;
+
+use crate::diff::{FileDiff, LineKind};
+use crate::{Comment, CommentType, Side};
+
+const IGNORE: &str = "b4sam:ignore";
+const IGNORE_NEXT_LINE: &str = "b4sam:ignore-next-line";
+/// What a directive must directly follow, so that the markers in prose and strings don't count
+const COMMENT_LEADERS: [&str; 4] = ["//", "#", "--", "/*"];
+const COMMENT_ENDS: [&str; 2] = ["*/", "-->"];
+
+/// A `b4sam:ignore` or `b4sam:ignore-next-line` comment in the reviewed code
+#[derive(Debug, Clone)]
+pub struct Directive {
+    pub path: String,
+    /// The line the directive applies to, in the new version of the file
+    pub line: u32,
+    /// The comment types to ignore, or `None` for all of them
+    pub types: Option<Vec<CommentType>>,
+}
+
+impl Directive {
+    fn ignores(&self, comment: &Comment) -> bool {
+        comment.anchored
+            && comment.side == Side::New
+            && comment.path == self.path
+            && (comment.start_line..=comment.end_line).contains(&self.line)
+            && self
+                .types
+                .as_ref()
+                .is_none_or(|types| types.contains(&comment.comment_type))
+    }
+
+    /// The directive as a line of the system prompt
+    fn describe(&self) -> String {
+        match &self.types {
+            Some(types) => {
+                let types: Vec<String> = types.iter().map(|t| t.to_string()).collect();
+                format!("- {}:{} ({} only)", self.path, self.line, types.join(", "))
+            }
+            None => format!("- {}:{}", self.path, self.line),
+        }
+    }
+}
+
+/// Finds the directives in the lines of the new files that are shown in the diff. Comments like
+/// `// b4sam:ignore LeftoverDebug` apply to their own line, and `# b4sam:ignore-next-line` to the
+/// line after it. Without any types, every type of comment is ignored. The directive must come
+/// right after the comment's `//`, `#`, `--` or `/*`.
+pub fn find(files: &[FileDiff]) -> Vec<Directive> {
+    let mut directives = Vec::new();
+    for file in files {
+        let Some(path) = &file.new_path else {
+            continue;
+        };
+        for line in file.lines() {
+            let Some(number) = line.new_line.filter(|_| line.kind != LineKind::Removed) else {
+                continue;
+            };
+            let (rest, line) = if let Some(rest) = after_marker(&line.text, IGNORE_NEXT_LINE) {
+                (rest, number + 1)
+            } else if let Some(rest) = after_marker(&line.text, IGNORE) {
+                (rest, number)
+            } else {
+                continue;
+            };
+
+            // stop at the end of the comment, or at the first word that isn't a type
+            let rest = COMMENT_ENDS
+                .iter()
+                .fold(rest, |rest, end| rest.split(end).next().unwrap_or_default());
+            let types: Vec<CommentType> = rest
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|word| !word.is_empty())
+                .map_while(|word| CommentType::from_str(word, true).ok())
+                .collect();
+            directives.push(Directive {
+                path: path.clone(),
+                line,
+                types: (!types.is_empty()).then_some(types),
+            });
+        }
+    }
+    directives
+}
+
+/// The text after `marker`, if it starts a comment rather than being in prose or a string
+fn after_marker<'a>(text: &'a str, marker: &str) -> Option<&'a str> {
+    text.match_indices(marker).find_map(|(i, _)| {
+        let before = text[..i].trim_end();
+        let rest = &text[i + marker.len()..];
+        (COMMENT_LEADERS
+            .iter()
+            .any(|leader| before.ends_with(leader))
+            && !rest.starts_with(|c: char| c.is_alphanumeric() || c == '-'))
+        .then_some(rest)
+    })
+}
+
+/// Instructions for the model to leave the ignored lines alone
+pub fn prompt(directives: &[Directive]) -> Option<String> {
+    if directives.is_empty() {
+        return None;
+    }
+
+    let lines: Vec<String> = directives.iter().map(Directive::describe).collect();
+    Some(format!(
+        "The author has marked these lines (in the new version of each file) with a `b4sam:ignore` directive. Do not leave comments on them, or only of other types where types are given:\n{}",
+        lines.join("\n")
+    ))
+}
+
+/// Drops the comments that a directive ignores, returning how many were dropped
+pub fn apply(comments: &mut Vec<Comment>, directives: &[Directive]) -> usize {
+    let before = comments.len();
+    comments.retain(|comment| !directives.iter().any(|d| d.ignores(comment)));
+    before - comments.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff;
+
+    const DIFF: &str = "diff --git a/src/a.rs b/src/a.rs\n--- a/src/a.rs\n+++ b/src/a.rs\n@@ -1,2 +1,8 @@\n let a = 1; // b4sam:ignore\n-old(); // b4sam:ignore\n+dbg!(a); // b4sam:ignore LeftoverDebug, Nitpick\n+/* b4sam:ignore-next-line Issue */\n+query(a);\n+/* b4sam:ignore Issue*/\n+let s = \"b4sam:ignore\";\n+// see the b4sam:ignore docs\n+# b4sam:ignore Nitpick because it is generated\n";
+
+    fn directives() -> Vec<(u32, Option<Vec<CommentType>>)> {
+        find(&diff::parse(DIFF))
+            .into_iter()
+            .map(|directive| {
+                assert_eq!(directive.path, "src/a.rs");
+                (directive.line, directive.types)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn directives_in_comments_are_found() {
+        assert_eq!(
+            directives(),
+            vec![
+                // without types, everything is ignored
+                (1, None),
+                // the removed line has no directive
+                (
+                    2,
+                    Some(vec![CommentType::LeftoverDebug, CommentType::Nitpick])
+                ),
+                (4, Some(vec![CommentType::Issue])),
+                // the list ends at `*/`, even without a space before it
+                (5, Some(vec![CommentType::Issue])),
+                // and at the first word that isn't a type
+                (8, Some(vec![CommentType::Nitpick])),
+            ]
+        );
+    }
+
+    #[test]
+    fn markers_must_start_a_comment() {
+        assert_eq!(
+            after_marker("x(); // b4sam:ignore Nitpick", IGNORE),
+            Some(" Nitpick")
+        );
+        assert_eq!(after_marker("-- b4sam:ignore", IGNORE), Some(""));
+        assert_eq!(after_marker("<!-- b4sam:ignore -->", IGNORE), Some(" -->"));
+        assert_eq!(after_marker("let s = \"b4sam:ignore\";", IGNORE), None);
+        assert_eq!(after_marker("// see b4sam:ignore", IGNORE), None);
+        assert_eq!(after_marker("// b4sam:ignore-next-line", IGNORE), None);
+        assert_eq!(after_marker("// b4sam:ignored", IGNORE), None);
+    }
+}
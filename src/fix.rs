@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::{Comment, Side};
+
+/// Lines of unchanged context around each change in a patch
+const CONTEXT_LINES: usize = 3;
+
+/// A fix that was found in the working tree, ready to be previewed or applied
+#[derive(Debug)]
+pub struct Edit<'a> {
+    pub comment: &'a Comment,
+    /// The first replaced line, counting from 0
+    pub start: usize,
+    /// How many lines are replaced
+    pub len: usize,
+    /// The new lines, with their line endings
+    pub replacement: Vec<String>,
+}
+
+/// A file of the working tree, split into lines that keep their line endings
+pub struct SourceFile {
+    pub lines: Vec<String>,
+    newline: &'static str,
+}
+
+impl SourceFile {
+    pub fn read(root: &Path, path: &str) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(root.join(path))
+            .with_context(|| format!("Failed to read {}", path))?;
+        Ok(SourceFile::new(&contents))
+    }
+
+    fn new(contents: &str) -> Self {
+        SourceFile {
+            lines: contents.split_inclusive('\n').map(str::to_string).collect(),
+            newline: if contents.contains("\r\n") {
+                "\r\n"
+            } else {
+                "\n"
+            },
+        }
+    }
+}
+
+fn strip_newline(line: &str) -> &str {
+    line.trim_end_matches(['\n', '\r'])
+}
+
+/// Finds where the fix's `original` text is in the file, checking that it still matches.
+/// Models sometimes get line numbers slightly wrong, so the closest match to the comment wins.
+pub fn locate<'a>(comment: &'a Comment, file: &SourceFile) -> Result<Edit<'a>, &'static str> {
+    let Some(fix) = &comment.fix else {
+        return Err("no fix");
+    };
+    if comment.side != Side::New {
+        return Err("the fix is for removed lines");
+    }
+
+    let original: Vec<&str> = fix.original.lines().map(str::trim_end).collect();
+    if original.iter().all(|line| line.is_empty()) {
+        return Err("the fix does not say which text it replaces");
+    }
+
+    let reported = comment.start_line.saturating_sub(1) as usize;
+    let start = (0..=file.lines.len().saturating_sub(original.len()))
+        .filter(|&start| {
+            file.lines.len() - start >= original.len()
+                && file.lines[start..]
+                    .iter()
+                    .zip(&original)
+                    .all(|(line, original)| strip_newline(line).trim_end() == *original)
+        })
+        .min_by_key(|start| start.abs_diff(reported))
+        .ok_or("the original text is not in the file")?;
+
+    let len = original.len();
+    // keep the file's missing newline at the end if the last line is replaced
+    let at_end = start + len == file.lines.len()
+        && !file.lines.last().is_some_and(|line| line.ends_with('\n'));
+    let mut replacement: Vec<String> = fix
+        .replacement
+        .lines()
+        .map(|line| format!("{}{}", line, file.newline))
+        .collect();
+    if at_end {
+        if let Some(last) = replacement.last_mut() {
+            last.truncate(strip_newline(last).len());
+        }
+    }
+
+    Ok(Edit {
+        comment,
+        start,
+        len,
+        replacement,
+    })
+}
+
+/// The file's lines with the edits made. The edits must be sorted and must not overlap.
+pub fn apply(file: &SourceFile, edits: &[&Edit]) -> String {
+    let mut result = String::new();
+    let mut next = 0;
+    for edit in edits {
+        result.extend(file.lines[next..edit.start].iter().map(String::as_str));
+        result.extend(edit.replacement.iter().map(String::as_str));
+        next = edit.start + edit.len;
+    }
+    result.extend(file.lines[next..].iter().map(String::as_str));
+    result
+}
+
+/// A git-style unified diff of the edits to a file. The edits must be sorted and must not overlap.
+pub fn unified_diff(path: &str, file: &SourceFile, edits: &[&Edit]) -> String {
+    let mut diff = format!(
+        "diff --git a/{path} b/{path}\n--- a/{path}\n+++ b/{path}\n",
+        path = path
+    );
+
+    // edits whose context would touch are shown in one hunk
+    let mut groups: Vec<Vec<&Edit>> = Vec::new();
+    for edit in edits {
+        match groups.last_mut() {
+            Some(group)
+                if group.last().is_some_and(|last| {
+                    edit.start <= last.start + last.len + 2 * CONTEXT_LINES
+                }) =>
+            {
+                group.push(edit)
+            }
+            _ => groups.push(vec![edit]),
+        }
+    }
+
+    // how many more lines the new file has than the old one, before the current hunk
+    let mut offset: isize = 0;
+    for group in groups {
+        let (first, last) = (group[0], group[group.len() - 1]);
+        let from = first.start.saturating_sub(CONTEXT_LINES);
+        let to = (last.start + last.len + CONTEXT_LINES).min(file.lines.len());
+        let added: isize = group
+            .iter()
+            .map(|edit| edit.replacement.len() as isize - edit.len as isize)
+            .sum();
+        let old_len = to - from;
+        let new_len = (old_len as isize + added) as usize;
+
+        let start = |len: usize, offset: isize| {
+            // an empty range starts at the line before it
+            let start = from as isize + offset + if len == 0 { 0 } else { 1 };
+            start.max(0)
+        };
+        diff.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            start(old_len, 0),
+            old_len,
+            start(new_len, offset),
+            new_len
+        ));
+
+        let mut next = from;
+        for edit in &group {
+            push_lines(&mut diff, ' ', &file.lines[next..edit.start]);
+            push_lines(
+                &mut diff,
+                '-',
+                &file.lines[edit.start..edit.start + edit.len],
+            );
+            push_lines(&mut diff, '+', &edit.replacement);
+            next = edit.start + edit.len;
+        }
+        push_lines(&mut diff, ' ', &file.lines[next..to]);
+        offset += added;
+    }
+
+    diff
+}
+
+fn push_lines(diff: &mut String, prefix: char, lines: &[String]) {
+    for line in lines {
+        diff.push(prefix);
+        diff.push_str(strip_newline(line));
+        diff.push('\n');
+        if !line.ends_with('\n') {
+            diff.push_str("\\ No newline at end of file\n");
+        }
+    }
+}
+
+/// Groups the edits by file, sorted by position, leaving out any that overlap an earlier edit.
+/// Returns the groups and the edits that were left out.
+pub fn by_file<'a, 'b>(
+    edits: &'b [(String, Edit<'a>)],
+) -> (BTreeMap<&'b str, Vec<&'b Edit<'a>>>, Vec<&'b Edit<'a>>) {
+    let mut files: BTreeMap<&str, Vec<&Edit>> = BTreeMap::new();
+    for (path, edit) in edits {
+        files.entry(path.as_str()).or_default().push(edit);
+    }
+    let mut overlapping = Vec::new();
+    for edits in files.values_mut() {
+        edits.sort_by_key(|edit| edit.start);
+        let mut end = 0;
+        edits.retain(|edit| {
+            let keep = edit.start >= end;
+            if keep {
+                end = edit.start + edit.len;
+            } else {
+                overlapping.push(*edit);
+            }
+            keep
+        });
+    }
+    (files, overlapping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(line: u32, original: &str, replacement: &str) -> Comment {
+        Comment::on("f.rs", line, line).with_fix(original, replacement)
+    }
+
+    fn numbered(lines: usize) -> SourceFile {
+        let lines: Vec<String> = (1..=lines).map(|i| format!("line {}", i)).collect();
+        SourceFile::new(&(lines.join("\n") + "\n"))
+    }
+
+    #[test]
+    fn fixes_are_located_near_the_reported_line() {
+        let file = SourceFile::new("a\ndbg!()\nb\ndbg!()\nc\n");
+        let fix = comment(5, "dbg!()", "");
+        let edit = locate(&fix, &file).unwrap();
+        assert_eq!((edit.start, edit.len), (3, 1));
+
+        let missing = comment(2, "println!()", "");
+        assert!(locate(&missing, &file).is_err());
+    }
+
+    #[test]
+    fn a_fix_at_the_start_of_a_file() {
+        let file = numbered(10);
+        let fix = comment(1, "line 1", "first");
+        let edit = locate(&fix, &file).unwrap();
+        assert_eq!(
+            unified_diff("f.rs", &file, &[&edit]),
+            "diff --git a/f.rs b/f.rs\n--- a/f.rs\n+++ b/f.rs\n@@ -1,4 +1,4 @@\n-line 1\n+first\n line 2\n line 3\n line 4\n"
+        );
+        assert!(apply(&file, &[&edit]).starts_with("first\nline 2\n"));
+    }
+
+    #[test]
+    fn a_fix_at_the_end_of_a_file_without_a_trailing_newline() {
+        let file = SourceFile::new("a\nb\nc");
+        let fix = comment(3, "c", "d");
+        let edit = locate(&fix, &file).unwrap();
+        assert_eq!(edit.replacement, vec!["d".to_string()]);
+        assert_eq!(apply(&file, &[&edit]), "a\nb\nd");
+        assert_eq!(
+            unified_diff("f.rs", &file, &[&edit]),
+            "diff --git a/f.rs b/f.rs\n--- a/f.rs\n+++ b/f.rs\n@@ -1,3 +1,3 @@\n a\n b\n-c\n\\ No newline at end of file\n+d\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn an_empty_replacement_deletes_the_lines() {
+        let file = numbered(3);
+        let fix = comment(2, "line 2", "");
+        let edit = locate(&fix, &file).unwrap();
+        assert!(edit.replacement.is_empty());
+        assert_eq!(apply(&file, &[&edit]), "line 1\nline 3\n");
+        assert_eq!(
+            unified_diff("f.rs", &file, &[&edit]),
+            "diff --git a/f.rs b/f.rs\n--- a/f.rs\n+++ b/f.rs\n@@ -1,3 +1,2 @@\n line 1\n-line 2\n line 3\n"
+        );
+    }
+
+    #[test]
+    fn overlapping_fixes_keep_the_first() {
+        let file = numbered(20);
+        let first = comment(2, "line 2\nline 3", "two and three");
+        let overlapping = comment(3, "line 3", "three");
+        let later = comment(15, "line 15", "fifteen");
+        let edits: Vec<(String, Edit)> = [&later, &overlapping, &first]
+            .into_iter()
+            .map(|fix| ("f.rs".to_string(), locate(fix, &file).unwrap()))
+            .collect();
+
+        let (files, overlapping) = by_file(&edits);
+        let kept: Vec<usize> = files["f.rs"].iter().map(|edit| edit.start).collect();
+        assert_eq!(kept, vec![1, 14]);
+        assert_eq!(overlapping.len(), 1);
+        assert_eq!(overlapping[0].start, 2);
+        // edits far enough apart are shown in separate hunks
+        let diff = unified_diff("f.rs", &file, &files["f.rs"]);
+        assert_eq!(diff.matches("\n@@ ").count(), 2);
+        assert!(diff.contains("@@ -1,6 +1,5 @@\n"));
+        assert!(diff.contains("@@ -12,7 +11,7 @@\n"));
+    }
+}
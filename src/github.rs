@@ -0,0 +1,197 @@
+use anyhow::Context;
+
+use crate::diff::{self, FileDiff};
+use crate::output::RunMetadata;
+use crate::{git_output, Comment, Review, Side};
+
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// The pull request to post a review on
+pub struct PullRequest {
+    pub api_url: String,
+    pub token: String,
+    pub owner: String,
+    pub repo: String,
+    pub number: u64,
+}
+
+#[derive(serde::Deserialize)]
+struct PullRequestSummary {
+    number: u64,
+}
+
+#[derive(serde::Serialize)]
+struct ReviewRequest {
+    commit_id: String,
+    body: String,
+    event: &'static str,
+    comments: Vec<ReviewComment>,
+}
+
+#[derive(serde::Serialize)]
+struct ReviewComment {
+    path: String,
+    /// The first line of a multi-line comment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_line: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_side: Option<&'static str>,
+    /// The last line the comment is about
+    line: u32,
+    side: &'static str,
+    body: String,
+}
+
+#[derive(serde::Deserialize)]
+struct ReviewResponse {
+    html_url: Option<String>,
+}
+
+impl PullRequest {
+    /// Finds the pull request to post on, from `--pr` or else the open pull request for the
+    /// current branch. The repository comes from `--repo`, or else the URL of `remote`.
+    pub async fn find(
+        api_url: &str,
+        token: String,
+        repo: Option<&str>,
+        remote: &str,
+        number: Option<u64>,
+    ) -> anyhow::Result<Self> {
+        let slug = match repo {
+            Some(repo) => repo.to_string(),
+            None => {
+                let url = git_output(&["remote", "get-url", remote])?;
+                crate::remote_path(&url)
+                    .with_context(|| format!("Could not find the repository in {}", url))?
+            }
+        };
+        let (owner, repo) = slug
+            .split_once('/')
+            .with_context(|| format!("Expected a repository like owner/repo, got {}", slug))?;
+
+        let mut pull_request = PullRequest {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            token,
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            number: 0,
+        };
+
+        pull_request.number = match number {
+            Some(number) => number,
+            None => pull_request.number_for_current_branch().await?,
+        };
+
+        Ok(pull_request)
+    }
+
+    async fn number_for_current_branch(&self) -> anyhow::Result<u64> {
+        let branch = git_output(&["rev-parse", "--abbrev-ref", "HEAD"])?;
+        let url = format!("{}/repos/{}/{}/pulls", self.api_url, self.owner, self.repo);
+        let head = format!("{}:{}", self.owner, branch);
+        let request = reqwest::Client::new()
+            .get(&url)
+            .query(&[("state", "open"), ("head", head.as_str())]);
+        let pulls: Vec<PullRequestSummary> = self.send(request).await?;
+
+        pulls
+            .first()
+            .map(|pull| pull.number)
+            .with_context(|| format!("No open pull request found for branch {}", branch))
+    }
+
+    /// Posts the comments as a single review. Comments on lines that GitHub shows in the pull
+    /// request's diff (the changed lines and a few around them) become inline comments; the rest
+    /// are listed in the review body, since a comment on any other line fails the whole review.
+    pub async fn post_review(
+        &self,
+        review: &Review,
+        metadata: &RunMetadata,
+        files: &[FileDiff],
+    ) -> anyhow::Result<Option<String>> {
+        let (inline, unanchored): (Vec<&Comment>, Vec<&Comment>) = review
+            .comments
+            .iter()
+            .partition(|comment| diff::postable(comment, files).is_some());
+
+        let mut body = format!(
+            "b4sam left {} comment(s) using {}.",
+            review.comments.len(),
+            metadata.model
+        );
+        if !unanchored.is_empty() {
+            body.push_str("\n\nThese comments could not be placed on the diff:\n");
+            for comment in &unanchored {
+                body.push_str(&format!(
+                    "\n- `{}` **{}**: {}",
+                    comment.location(),
+                    comment.comment_type,
+                    comment.comment
+                ));
+            }
+        }
+        if !metadata.skipped_files.is_empty() {
+            body.push_str(&format!(
+                "\n\nSkipped (larger than the token budget): {}",
+                metadata.skipped_files.join(", ")
+            ));
+        }
+
+        let request = ReviewRequest {
+            commit_id: git_output(&["rev-parse", "HEAD"])?,
+            body,
+            event: "COMMENT",
+            comments: inline
+                .into_iter()
+                .map(|comment| {
+                    let side = match comment.side {
+                        Side::Old => "LEFT",
+                        Side::New => "RIGHT",
+                    };
+                    let multi_line = comment.end_line > comment.start_line;
+                    ReviewComment {
+                        path: comment.path.clone(),
+                        start_line: multi_line.then_some(comment.start_line),
+                        start_side: multi_line.then_some(side),
+                        line: comment.end_line.max(comment.start_line),
+                        side,
+                        body: format!("**{}**: {}", comment.comment_type, comment.comment),
+                    }
+                })
+                .collect(),
+        };
+
+        let url = format!(
+            "{}/repos/{}/{}/pulls/{}/reviews",
+            self.api_url, self.owner, self.repo, self.number
+        );
+        let response: ReviewResponse = self
+            .send(reqwest::Client::new().post(&url).json(&request))
+            .await?;
+
+        Ok(response.html_url)
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> anyhow::Result<T> {
+        let response = request
+            .bearer_auth(&self.token)
+            .header("Accept", "application/vnd.github+json")
+            .header("X-GitHub-Api-Version", "2022-11-28")
+            .header("User-Agent", concat!("b4sam/", env!("CARGO_PKG_VERSION")))
+            .send()
+            .await
+            .context("Failed to send request to GitHub")?;
+
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            anyhow::bail!("GitHub API returned {}: {}", status, text);
+        }
+
+        serde_json::from_str(&text)
+            .with_context(|| format!("Unexpected response from GitHub API: {}", text))
+    }
+}
@@ -0,0 +1,245 @@
+use anyhow::Context;
+
+use crate::diff::{self, FileDiff};
+use crate::output::RunMetadata;
+use crate::{git_output, Comment, Review, Side};
+
+pub const DEFAULT_URL: &str = "https://gitlab.com";
+
+/// The merge request to post discussions on
+pub struct MergeRequest {
+    pub url: String,
+    pub token: String,
+    /// The project's path, like `group/subgroup/project`, or its numeric ID
+    pub project: String,
+    pub iid: u64,
+}
+
+#[derive(serde::Deserialize)]
+struct MergeRequestSummary {
+    iid: u64,
+}
+
+/// The revisions a diff position refers to
+pub struct DiffRefs {
+    /// The merge-base the changes were diffed against
+    pub base_sha: String,
+    /// The commit on the target branch the diff starts from. b4sam diffs from the merge-base, so
+    /// this is the same as `base_sha`.
+    pub start_sha: String,
+    pub head_sha: String,
+}
+
+#[derive(serde::Serialize)]
+struct Position<'a> {
+    position_type: &'static str,
+    base_sha: &'a str,
+    start_sha: &'a str,
+    head_sha: &'a str,
+    old_path: &'a str,
+    new_path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old_line: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new_line: Option<u32>,
+}
+
+#[derive(serde::Serialize)]
+struct Discussion<'a> {
+    body: String,
+    position: Position<'a>,
+}
+
+#[derive(serde::Serialize)]
+struct Note {
+    body: String,
+}
+
+impl DiffRefs {
+    /// Resolves the revisions of a diff taken between `base` and `HEAD`
+    pub fn new(base: &str) -> anyhow::Result<Self> {
+        let base_sha = git_output(&["rev-parse", base])?;
+        Ok(DiffRefs {
+            start_sha: base_sha.clone(),
+            base_sha,
+            head_sha: git_output(&["rev-parse", "HEAD"])?,
+        })
+    }
+}
+
+impl MergeRequest {
+    /// Finds the merge request to post on, from `--mr` or else the open merge request for the
+    /// current branch. The project comes from `--project`, or else the URL of `remote`.
+    pub async fn find(
+        url: &str,
+        token: String,
+        project: Option<&str>,
+        remote: &str,
+        iid: Option<u64>,
+    ) -> anyhow::Result<Self> {
+        let project = match project {
+            Some(project) => project.to_string(),
+            None => {
+                let remote_url = git_output(&["remote", "get-url", remote])?;
+                crate::remote_path(&remote_url)
+                    .with_context(|| format!("Could not find the project in {}", remote_url))?
+            }
+        };
+
+        let mut merge_request = MergeRequest {
+            url: url.trim_end_matches('/').to_string(),
+            token,
+            project,
+            iid: 0,
+        };
+
+        merge_request.iid = match iid {
+            Some(iid) => iid,
+            None => merge_request.iid_for_current_branch().await?,
+        };
+
+        Ok(merge_request)
+    }
+
+    fn project_url(&self) -> String {
+        format!(
+            "{}/api/v4/projects/{}",
+            self.url,
+            self.project.replace('/', "%2F")
+        )
+    }
+
+    async fn iid_for_current_branch(&self) -> anyhow::Result<u64> {
+        let branch = git_output(&["rev-parse", "--abbrev-ref", "HEAD"])?;
+        let request = reqwest::Client::new()
+            .get(format!("{}/merge_requests", self.project_url()))
+            .query(&[("state", "opened"), ("source_branch", branch.as_str())]);
+        let merge_requests: Vec<MergeRequestSummary> = self.send(request).await?;
+
+        merge_requests
+            .first()
+            .map(|merge_request| merge_request.iid)
+            .with_context(|| format!("No open merge request found for branch {}", branch))
+    }
+
+    /// Starts a discussion on the diff for each comment on a line GitLab shows in the merge
+    /// request's diff (the changed lines and a few around them), and leaves a note summarizing the
+    /// review with the comments that could not be positioned, including any GitLab rejected.
+    /// Returns a warning for each discussion GitLab rejected.
+    pub async fn post_review(
+        &self,
+        review: &Review,
+        metadata: &RunMetadata,
+        files: &[FileDiff],
+        refs: &DiffRefs,
+    ) -> anyhow::Result<Vec<String>> {
+        let discussions_url = format!(
+            "{}/merge_requests/{}/discussions",
+            self.project_url(),
+            self.iid
+        );
+
+        let mut unpositioned = Vec::new();
+        let mut rejected = Vec::new();
+        for comment in &review.comments {
+            let Some(position) = position(comment, files, refs) else {
+                unpositioned.push(comment);
+                continue;
+            };
+            let discussion = Discussion {
+                body: format!("**{}**: {}", comment.comment_type, comment.comment),
+                position,
+            };
+            let posted: anyhow::Result<serde::de::IgnoredAny> = self
+                .send(
+                    reqwest::Client::new()
+                        .post(&discussions_url)
+                        .json(&discussion),
+                )
+                .await;
+            if let Err(e) = posted {
+                rejected.push(format!(
+                    "Could not start a discussion on {}: {:#}",
+                    comment.location(),
+                    e
+                ));
+                unpositioned.push(comment);
+            }
+        }
+
+        let mut body = format!(
+            "b4sam left {} comment(s) using {}.",
+            review.comments.len(),
+            metadata.model
+        );
+        if !unpositioned.is_empty() {
+            body.push_str("\n\nThese comments could not be placed on the diff:\n");
+            for comment in &unpositioned {
+                body.push_str(&format!(
+                    "\n- `{}` **{}**: {}",
+                    comment.location(),
+                    comment.comment_type,
+                    comment.comment
+                ));
+            }
+        }
+        if !metadata.skipped_files.is_empty() {
+            body.push_str(&format!(
+                "\n\nSkipped (larger than the token budget): {}",
+                metadata.skipped_files.join(", ")
+            ));
+        }
+
+        let notes_url = format!("{}/merge_requests/{}/notes", self.project_url(), self.iid);
+        let _: serde::de::IgnoredAny = self
+            .send(reqwest::Client::new().post(&notes_url).json(&Note { body }))
+            .await?;
+
+        Ok(rejected)
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> anyhow::Result<T> {
+        let response = request
+            .header("PRIVATE-TOKEN", &self.token)
+            .send()
+            .await
+            .context("Failed to send request to GitLab")?;
+
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            anyhow::bail!("GitLab API returned {}: {}", status, text);
+        }
+
+        serde_json::from_str(&text)
+            .with_context(|| format!("Unexpected response from GitLab API: {}", text))
+    }
+}
+
+/// GitLab positions unchanged lines by both their old and new line numbers, added lines by
+/// their new line number and removed lines by their old line number
+fn position<'a>(
+    comment: &Comment,
+    files: &'a [FileDiff],
+    refs: &'a DiffRefs,
+) -> Option<Position<'a>> {
+    let file = diff::postable(comment, files)?;
+    let line = file.lines().find(|line| match comment.side {
+        Side::Old => line.old_line == Some(comment.start_line),
+        Side::New => line.new_line == Some(comment.start_line),
+    })?;
+
+    Some(Position {
+        position_type: "text",
+        base_sha: &refs.base_sha,
+        start_sha: &refs.start_sha,
+        head_sha: &refs.head_sha,
+        old_path: file.old_path.as_deref().unwrap_or(file.path()),
+        new_path: file.path(),
+        old_line: line.old_line,
+        new_line: line.new_line,
+    })
+}
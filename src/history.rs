@@ -0,0 +1,201 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+
+use crate::config::Config;
+use crate::diff::{self, FileDiff};
+use crate::{git_output, Comment, Side};
+
+/// The last review of a branch, kept so the next one can cover only what changed since
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct LastReview {
+    /// The commit that was reviewed
+    pub head: String,
+    /// The comments that were reported, with line numbers in `head`
+    pub comments: Vec<Comment>,
+    /// Whether `head` is no longer an ancestor of `HEAD`, e.g. after a rebase, so that the
+    /// changes since it may include changes from the rebase
+    #[serde(skip)]
+    pub rewritten: bool,
+}
+
+/// The checked-out branch, or `None` if `HEAD` is detached
+pub fn current_branch() -> Option<String> {
+    git_output(&["symbolic-ref", "--short", "-q", "HEAD"]).ok()
+}
+
+/// `.git/b4sam/last-review/<branch>.json`, which is never committed
+fn path(branch: &str) -> anyhow::Result<PathBuf> {
+    let dir = git_output(&[
+        "rev-parse",
+        "--path-format=absolute",
+        "--git-path",
+        "b4sam/last-review",
+    ])?;
+    Ok(PathBuf::from(dir).join(format!("{}.json", branch)))
+}
+
+/// The last review of `branch`, if it has been reviewed
+pub fn load(branch: &str) -> anyhow::Result<Option<LastReview>> {
+    let path = path(branch)?;
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    serde_json::from_str(&contents)
+        .map(Some)
+        .with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// The last review of the current branch, to continue from with `--since-last`. Fails if there
+/// is none or nothing was committed since.
+pub fn last_review() -> anyhow::Result<LastReview> {
+    let branch = current_branch().context("`--since-last` needs a branch to be checked out")?;
+    let mut last = load(&branch)?.with_context(|| {
+        format!(
+            "{} has not been reviewed yet; run `b4sam review` first",
+            branch
+        )
+    })?;
+
+    if git_output(&["rev-parse", "HEAD"])? == last.head {
+        anyhow::bail!(
+            "Nothing has been committed on {} since its last review",
+            branch
+        );
+    }
+    last.rewritten = git_output(&["merge-base", "--is-ancestor", &last.head, "HEAD"]).is_err();
+
+    Ok(last)
+}
+
+/// Records the comments of a review of `HEAD` on the current branch. Does nothing when `HEAD` is
+/// detached.
+pub fn record(comments: &[Comment]) -> anyhow::Result<()> {
+    let Some(branch) = current_branch() else {
+        return Ok(());
+    };
+    let last = LastReview {
+        head: git_output(&["rev-parse", "HEAD"])?,
+        comments: comments.to_vec(),
+        rewritten: false,
+    };
+
+    let path = path(&branch)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    std::fs::write(&path, serde_json::to_string_pretty(&last)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Moves the comments of the last review to where their lines are after the changes in `files`
+/// (the diff from the last reviewed commit). Comments on lines that were changed or removed are
+/// dropped, as are comments on removed lines of the original diff, which no longer exist.
+pub fn carry_forward(comments: Vec<Comment>, files: &[FileDiff]) -> Vec<Comment> {
+    comments
+        .into_iter()
+        .filter(|comment| comment.anchored && comment.side == Side::New)
+        .filter_map(|mut comment| {
+            let Some(file) = files
+                .iter()
+                .find(|file| file.old_path.as_deref() == Some(comment.path.as_str()))
+            else {
+                // the file hasn't changed
+                return Some(comment);
+            };
+
+            let path = file.new_path.clone()?;
+            if (comment.start_line..=comment.end_line).any(|line| file.new_line(line).is_none()) {
+                return None;
+            }
+            comment.start_line = file.new_line(comment.start_line)?;
+            comment.end_line = file.new_line(comment.end_line)?;
+            comment.path = path;
+            Some(comment)
+        })
+        .collect()
+}
+
+/// Moves comments on lines of `rev` to where those lines are in `HEAD`, dropping the ones on lines
+/// that have changed since
+pub fn move_to_head(
+    comments: Vec<Comment>,
+    rev: &str,
+    config: &Config,
+) -> anyhow::Result<Vec<Comment>> {
+    let mut args = vec![
+        "diff",
+        "--no-color",
+        "--src-prefix=a/",
+        "--dst-prefix=b/",
+        rev,
+        "HEAD",
+        "--",
+    ];
+    let pathspecs = config.pathspecs();
+    args.extend(pathspecs.iter().map(String::as_str));
+    let diff = git_output(&args)?;
+
+    Ok(carry_forward(comments, &diff::parse(&diff)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff;
+
+    fn lines(comments: &[Comment]) -> Vec<(&str, u32, u32)> {
+        comments
+            .iter()
+            .map(|c| (c.path.as_str(), c.start_line, c.end_line))
+            .collect()
+    }
+
+    #[test]
+    fn comments_move_with_their_lines() {
+        // two lines are added after line 4, line 8 is removed, and g.txt is renamed
+        let diff = "diff --git a/f.txt b/f.txt\n--- a/f.txt\n+++ b/f.txt\n@@ -3,3 +3,5 @@\n 3\n 4\n+a\n+b\n 5\n@@ -7,3 +9,2 @@\n 7\n-8\n 9\ndiff --git a/g.txt b/h.txt\nsimilarity index 100%\nrename from g.txt\nrename to h.txt\n";
+        let files = diff::parse(diff);
+
+        let comments = vec![
+            // above the changes
+            Comment::on("f.txt", 1, 2),
+            // inside a hunk, on unchanged lines
+            Comment::on("f.txt", 4, 5),
+            // below the changes
+            Comment::on("f.txt", 12, 12),
+            // on a deleted line
+            Comment::on("f.txt", 8, 8),
+            // across a deleted line
+            Comment::on("f.txt", 7, 9),
+            // in a renamed file
+            Comment::on("g.txt", 3, 3),
+            // in a file that didn't change
+            Comment::on("other.txt", 3, 3),
+        ];
+        assert_eq!(
+            lines(&carry_forward(comments, &files)),
+            vec![
+                ("f.txt", 1, 2),
+                ("f.txt", 4, 7),
+                ("f.txt", 13, 13),
+                ("h.txt", 3, 3),
+                ("other.txt", 3, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_on_removed_or_unlocated_lines_are_dropped() {
+        let mut removed = Comment::on("f.txt", 1, 1);
+        removed.side = Side::Old;
+        let mut unanchored = Comment::on("f.txt", 1, 1);
+        unanchored.anchored = false;
+
+        assert!(carry_forward(vec![removed, unanchored], &[]).is_empty());
+    }
+}
@@ -0,0 +1,239 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Context;
+use b4sam::Severity;
+use clap::ValueEnum;
+
+/// Marks hook scripts written by b4sam, so they are never mistaken for the user's own
+const MARKER: &str = "# b4sam-hook";
+/// Suffix of an existing hook that was moved aside to be run before b4sam
+const CHAINED_SUFFIX: &str = ".pre-b4sam";
+/// Setting this environment variable skips the review
+const SKIP_ENV: &str = "B4SAM_SKIP";
+
+/// The git hooks b4sam can run from
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Hook {
+    /// Review the committed changes before pushing
+    #[default]
+    PrePush,
+    /// Review the staged changes before committing
+    PreCommit,
+}
+
+/// What happens when a hook finds problems
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HookMode {
+    /// Abort the push or commit if there are comments at or above the `--fail-on` severity
+    #[default]
+    Blocking,
+    /// Show the review, but never abort
+    Advise,
+}
+
+impl Hook {
+    const ALL: [Hook; 2] = [Hook::PrePush, Hook::PreCommit];
+
+    fn name(self) -> &'static str {
+        match self {
+            Hook::PrePush => "pre-push",
+            Hook::PreCommit => "pre-commit",
+        }
+    }
+}
+
+/// The directory git runs hooks from, which is `core.hooksPath` if set
+fn hooks_dir() -> anyhow::Result<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--path-format=absolute", "--git-path", "hooks"])
+        .output()
+        .context("Failed to run `git rev-parse`")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Failed to find the hooks directory: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(PathBuf::from(
+        String::from_utf8_lossy(&output.stdout).trim(),
+    ))
+}
+
+fn is_ours(path: &Path) -> bool {
+    std::fs::read_to_string(path).is_ok_and(|script| script.contains(MARKER))
+}
+
+fn chained_path(path: &Path) -> PathBuf {
+    let mut chained = path.as_os_str().to_owned();
+    chained.push(CHAINED_SUFFIX);
+    PathBuf::from(chained)
+}
+
+/// Installs the hook, moving any existing hook aside so that it still runs first
+pub fn install(hook: Hook, mode: HookMode, fail_on: Severity) -> anyhow::Result<()> {
+    let dir = hooks_dir()?;
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(hook.name());
+
+    if path.exists() && !is_ours(&path) {
+        let chained = chained_path(&path);
+        if chained.exists() {
+            anyhow::bail!(
+                "Both {} and {} exist; remove one of them first",
+                path.display(),
+                chained.display()
+            );
+        }
+        std::fs::rename(&path, &chained)
+            .with_context(|| format!("Failed to move {} aside", path.display()))?;
+        println!(
+            "Moved the existing hook to {}; it will run before b4sam",
+            chained.display()
+        );
+    }
+
+    let exe = std::env::current_exe().context("Failed to find the b4sam executable")?;
+    std::fs::write(&path, script(hook, mode, fail_on, &exe))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("Failed to make {} executable", path.display()))?;
+    }
+
+    println!("Installed {} hook at {}", hook.name(), path.display());
+    Ok(())
+}
+
+/// Removes the hook, restoring the hook it was chained to
+pub fn uninstall(hook: Hook) -> anyhow::Result<()> {
+    let path = hooks_dir()?.join(hook.name());
+    if !is_ours(&path) {
+        anyhow::bail!("No b4sam {} hook is installed", hook.name());
+    }
+
+    std::fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    let chained = chained_path(&path);
+    if chained.exists() {
+        std::fs::rename(&chained, &path)
+            .with_context(|| format!("Failed to restore {}", chained.display()))?;
+        println!("Restored the previous {} hook", hook.name());
+    }
+
+    println!("Uninstalled {} hook", hook.name());
+    Ok(())
+}
+
+/// Prints whether each hook is installed, and how
+pub fn status() -> anyhow::Result<()> {
+    let dir = hooks_dir()?;
+    println!("Hooks directory: {}", dir.display());
+
+    for hook in Hook::ALL {
+        let path = dir.join(hook.name());
+        let status = match std::fs::read_to_string(&path) {
+            Ok(script) if script.contains(MARKER) => {
+                let mode = script
+                    .lines()
+                    .find_map(|line| line.strip_prefix("# b4sam-mode: "))
+                    .unwrap_or("unknown");
+                let mut status = format!("installed ({})", mode);
+                if chained_path(&path).exists() {
+                    status.push_str(", chained to the previous hook");
+                }
+                status
+            }
+            Ok(_) => "not installed (another hook is present)".to_string(),
+            Err(_) => "not installed".to_string(),
+        };
+        println!("{}: {}", hook.name(), status);
+    }
+
+    Ok(())
+}
+
+fn script(hook: Hook, mode: HookMode, fail_on: Severity, exe: &Path) -> String {
+    let fail_on = fail_on
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default();
+    let exe = shell_quote(&exe.to_string_lossy());
+    let staged = match hook {
+        Hook::PrePush => "",
+        Hook::PreCommit => " --staged",
+    };
+    let (mode_name, review) = match mode {
+        HookMode::Blocking => (
+            format!("blocking, --fail-on {}", fail_on),
+            format!("{} review{} --fail-on {}", exe, staged, fail_on),
+        ),
+        HookMode::Advise => ("advise".to_string(), format!("{} review{}", exe, staged)),
+    };
+    let action = match hook {
+        Hook::PrePush => "push",
+        Hook::PreCommit => "commit",
+    };
+
+    // pre-push gets the refs being pushed on stdin, which the chained hook needs too
+    let chain = match hook {
+        Hook::PrePush => format!(
+            r#"input=$(cat)
+if [ -x "$0{suffix}" ]; then
+    if [ -n "$input" ]; then printf '%s\n' "$input"; fi | "$0{suffix}" "$@" || exit $?
+fi"#,
+            suffix = CHAINED_SUFFIX
+        ),
+        Hook::PreCommit => format!(
+            r#"if [ -x "$0{suffix}" ]; then
+    "$0{suffix}" "$@" || exit $?
+fi"#,
+            suffix = CHAINED_SUFFIX
+        ),
+    };
+
+    let run = match mode {
+        HookMode::Blocking => format!(
+            r#"{review}
+status=$?
+if [ $status -eq 1 ]; then
+    echo "b4sam: aborting the {action}; set {skip}=1 to skip the review" >&2
+    exit 1
+elif [ $status -ne 0 ]; then
+    echo "b4sam: the review failed, continuing with the {action}" >&2
+fi"#,
+            skip = SKIP_ENV
+        ),
+        // status 1 only means there were findings, which the review has already shown
+        HookMode::Advise => format!(
+            r#"{review}
+if [ $? -gt 1 ]; then
+    echo "b4sam: the review failed, continuing with the {action}" >&2
+fi"#
+        ),
+    };
+
+    format!(
+        r#"#!/bin/sh
+{MARKER}
+# b4sam-mode: {mode_name}
+# Installed by `b4sam hook install`; remove it with `b4sam hook uninstall`.
+# Set {skip}=1 to skip the review.
+
+{chain}
+
+if [ -n "${skip}" ]; then
+    exit 0
+fi
+
+{run}
+exit 0
+"#,
+        skip = SKIP_ENV
+    )
+}
+
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
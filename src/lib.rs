@@ -0,0 +1,271 @@
+//! Reviews code changes with an LLM.
+//!
+//! Collect the changes from a [`DiffSource`], create a [`Reviewer`] for the configured provider
+//! with [`reviewer::reviewer`], and review them with [`dyn Reviewer::review_changes`]:
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use b4sam::config::Config;
+//! use b4sam::DiffSource;
+//!
+//! let config = Config::load()?;
+//! let changes = DiffSource::Committed { against: None }.changes(&config)?;
+//! let reviewer = b4sam::reviewer::reviewer(&config)?;
+//! // pass `Some(&b4sam::baseline::Baseline::load()?)` to leave out the comments in the baseline file
+//! let (review, metadata) = reviewer
+//!     .review_changes(None, &changes, &config, None, false)
+//!     .await?;
+//! for comment in &review.comments {
+//!     println!("{}: {}", comment.location(), comment.comment);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::process::Command;
+
+use anyhow::Context;
+
+mod anchor;
+pub mod baseline;
+pub mod cache;
+pub mod changes;
+mod chunk;
+pub mod config;
+mod context;
+pub mod cost;
+pub mod describe;
+pub mod diff;
+mod directive;
+pub mod fix;
+pub mod github;
+pub mod gitlab;
+pub mod history;
+pub mod messages;
+pub mod output;
+mod replay;
+pub mod review;
+pub mod reviewer;
+mod sarif;
+pub mod usage;
+
+pub use changes::{Changes, Commit, DiffSource, Target};
+pub use output::RunMetadata;
+pub use reviewer::Reviewer;
+
+#[derive(
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+    clap::ValueEnum,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+)]
+#[value(rename_all = "PascalCase")]
+pub enum CommentType {
+    Nitpick,
+    LeftoverDebug,
+    UnnecessaryComment,
+    StyleIssue,
+    Question,
+    Issue,
+    Suggestion,
+    Idea,
+}
+
+/// How serious a comment is, used to decide whether a run should fail
+#[derive(
+    serde::Deserialize, clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl CommentType {
+    pub const ALL: [CommentType; 8] = [
+        CommentType::Nitpick,
+        CommentType::LeftoverDebug,
+        CommentType::UnnecessaryComment,
+        CommentType::StyleIssue,
+        CommentType::Question,
+        CommentType::Issue,
+        CommentType::Suggestion,
+        CommentType::Idea,
+    ];
+
+    pub fn severity(self) -> Severity {
+        match self {
+            CommentType::Issue | CommentType::LeftoverDebug => Severity::Error,
+            CommentType::StyleIssue | CommentType::Nitpick | CommentType::UnnecessaryComment => {
+                Severity::Warning
+            }
+            CommentType::Idea | CommentType::Question | CommentType::Suggestion => Severity::Info,
+        }
+    }
+}
+
+impl std::fmt::Display for CommentType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommentType::Nitpick => write!(f, "Nitpick"),
+            CommentType::LeftoverDebug => write!(f, "LeftoverDebug"),
+            CommentType::UnnecessaryComment => write!(f, "UnnecessaryComment"),
+            CommentType::StyleIssue => write!(f, "StyleIssue"),
+            CommentType::Question => write!(f, "Question"),
+            CommentType::Issue => write!(f, "Issue"),
+            CommentType::Suggestion => write!(f, "Suggestion"),
+            CommentType::Idea => write!(f, "Idea"),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone)]
+pub struct Comment {
+    pub comment_type: CommentType,
+    /// Path of the file, as in the diff header but without the `a/` or `b/` prefix
+    pub path: String,
+    /// First line the comment is about, numbered as in the version of the file given by `side`
+    pub start_line: u32,
+    /// Last line the comment is about (the same as `start_line` for a single line)
+    pub end_line: u32,
+    /// Which version of the file the line numbers refer to: `Old` for removed lines, `New` otherwise
+    pub side: Side,
+    /// The line of code being commented on, quoted exactly
+    pub line: String,
+    pub comment: String,
+    /// A replacement for the lines from `start_line` to `end_line`, when there is an obvious mechanical fix
+    #[serde(default)]
+    pub fix: Option<Fix>,
+    /// Whether the location was found in the diff
+    #[serde(default)]
+    #[schemars(skip)]
+    pub anchored: bool,
+}
+
+impl Comment {
+    /// A human-readable location like `src/main.rs:30-32`
+    pub fn location(&self) -> String {
+        let lines = if self.end_line > self.start_line {
+            format!("{}-{}", self.start_line, self.end_line)
+        } else {
+            self.start_line.to_string()
+        };
+        match self.side {
+            Side::New => format!("{}:{}", self.path, lines),
+            Side::Old => format!("{}:{} (before the change)", self.path, lines),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Comment {
+    /// An anchored comment on lines of the new version of `path`, for tests
+    pub(crate) fn on(path: &str, start_line: u32, end_line: u32) -> Self {
+        Comment {
+            comment_type: CommentType::Nitpick,
+            path: path.to_string(),
+            start_line,
+            end_line,
+            side: Side::New,
+            line: String::new(),
+            comment: String::new(),
+            fix: None,
+            anchored: true,
+        }
+    }
+
+    pub(crate) fn quoting(mut self, line: &str) -> Self {
+        self.line = line.to_string();
+        self
+    }
+
+    pub(crate) fn with_fix(mut self, original: &str, replacement: &str) -> Self {
+        self.fix = Some(Fix {
+            original: original.to_string(),
+            replacement: replacement.to_string(),
+        });
+        self
+    }
+}
+
+#[derive(
+    serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone, Copy, PartialEq, Eq,
+)]
+pub enum Side {
+    Old,
+    New,
+}
+
+impl Side {
+    pub fn other(self) -> Side {
+        match self {
+            Side::Old => Side::New,
+            Side::New => Side::Old,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug, Clone)]
+pub struct Fix {
+    /// The current text of the lines from `start_line` to `end_line` of the new file, copied exactly
+    pub original: String,
+    /// The text to replace those lines with (empty to delete them)
+    pub replacement: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema, Debug)]
+pub struct Review {
+    pub comments: Vec<Comment>,
+}
+
+/// The top level of the repository containing the current directory, if there is one
+pub fn repo_root() -> Option<std::path::PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().into())
+}
+
+/// Runs git and returns its trimmed stdout, failing if git does
+pub(crate) fn git_output(args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .context("Failed to run `git`")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The repository path of a remote URL, e.g. `owner/repo` for `git@github.com:owner/repo.git`
+pub(crate) fn remote_path(url: &str) -> Option<String> {
+    let path = match url.split_once("://") {
+        // ssh://git@host:22/owner/repo.git or https://host/owner/repo.git
+        Some((_, rest)) => rest.split_once('/')?.1,
+        // git@host:owner/repo.git
+        None => url.split_once(':')?.1,
+    };
+    let path = path.trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+
+    (!path.is_empty()).then(|| path.to_string())
+}
@@ -1,111 +1,34 @@
-use std::process::Command;
+use std::process::ExitCode;
 
 use anyhow::Context;
+use b4sam::config::Config;
+use b4sam::output::{self, print_review, OutputFormat, RunMetadata};
+use b4sam::review::ReviewPlan;
+use b4sam::{
+    baseline, cache, changes, describe, diff, github, gitlab, history, messages, review, reviewer,
+    usage, Changes, Comment, DiffSource, Review, Severity,
+};
 use clap::{Parser, Subcommand};
-use tysm::chat_completions::ChatClient;
-
-#[derive(serde::Deserialize, schemars::JsonSchema, Debug)]
-enum CommentType {
-    Nitpick,
-    LeftoverDebug,
-    UnnecessaryComment,
-    StyleIssue,
-    Question,
-    Issue,
-    Suggestion,
-    Idea,
-}
-
-impl std::fmt::Display for CommentType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            CommentType::Nitpick => write!(f, "Nitpick"),
-            CommentType::LeftoverDebug => write!(f, "LeftoverDebug"),
-            CommentType::UnnecessaryComment => write!(f, "UnnecessaryComment"),
-            CommentType::StyleIssue => write!(f, "StyleIssue"),
-            CommentType::Question => write!(f, "Question"),
-            CommentType::Issue => write!(f, "Issue"),
-            CommentType::Suggestion => write!(f, "Suggestion"),
-            CommentType::Idea => write!(f, "Idea"),
-        }
-    }
-}
-
-#[derive(serde::Deserialize, schemars::JsonSchema, Debug)]
-struct Comment {
-    comment_type: CommentType,
-    r#in: String,
-    line: String,
-    comment: String,
-}
-
-#[derive(serde::Deserialize, schemars::JsonSchema, Debug)]
-struct Review {
-    comments: Vec<Comment>,
-}
-
-fn get_changes(against: Option<&str>) -> anyhow::Result<String> {
-    // Validate the against revision if provided
-    if let Some(rev) = against {
-        let validate = Command::new("git")
-            .args(["rev-parse", "--verify", rev])
-            .output();
 
-        if !matches!(validate, Ok(ref o) if o.status.success()) {
-            anyhow::bail!("Invalid git revision: {}", rev);
-        }
-    }
+mod apply;
+mod hook;
+mod report;
+mod tui;
 
-    let base = if let Some(commit) = against {
-        commit.to_string()
-    } else {
-        // Try with origin/main first
-        let mut merge_base_output = Command::new("git")
-            .args(["merge-base", "origin/main", "HEAD"])
-            .output();
-
-        // If that fails, try with origin/master
-        if !matches!(merge_base_output, Ok(ref o) if o.status.success()) {
-            merge_base_output = Command::new("git")
-                .args(["merge-base", "origin/master", "HEAD"])
-                .output();
-        }
-
-        let merge_base_output = merge_base_output.context("Failed to run `git merge-base`")?;
-        let merge_base = String::from_utf8_lossy(&merge_base_output.stdout)
-            .trim()
-            .to_string();
-
-        if merge_base.is_empty() {
-            anyhow::bail!("Failed to find merge base with origin/main or origin/master");
-        }
-
-        merge_base
-    };
-
-    // Get the diff between the base and the current HEAD, excluding Cargo.lock and large JSON/CSV files
-    let diff_output = Command::new("git")
-        .args([
-            "diff", "-U30", /* give the model 30 lines of context for the change */
-            &base, "HEAD", "--", ".", ":(exclude)Cargo.lock", ":(exclude)*.json", ":(exclude)*.csv",
-        ])
-        .output()
-        .context("Failed to run `git diff`")?;
-
-    if !diff_output.status.success() {
-        anyhow::bail!("`git diff` failed with status: {}", diff_output.status);
-    }
-
-    if diff_output.stdout.is_empty() {
-        anyhow::bail!("No changes found");
-    }
-
-    Ok(String::from_utf8_lossy(&diff_output.stdout).to_string())
-}
+/// Exit code when there are comments at or above the `--fail-on` severity
+const EXIT_FINDINGS: u8 = 1;
+/// Exit code when b4sam itself fails, e.g. because of a git or LLM error
+const EXIT_FAILURE: u8 = 2;
 
 /// CLI tool for AI-powered code reviews
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    after_help = "Exit status is 0 on success, 1 if there are comments at or above the --fail-on severity, and 2 if b4sam failed."
+)]
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
@@ -113,6 +36,9 @@ struct Cli {
     /// Show verbose output
     #[arg(short, long)]
     verbose: bool,
+
+    #[command(flatten)]
+    config: Config,
 }
 
 #[derive(Subcommand)]
@@ -123,102 +49,786 @@ enum Commands {
         #[arg(short, long)]
         prompt: Option<String>,
 
+        /// Output format for the review results
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+
+        /// Go through the comments in a terminal UI before printing the ones you keep
+        #[arg(short, long)]
+        interactive: bool,
+
+        /// Review each commit since the base revision on its own, with its message
+        #[arg(long, conflicts_with_all = ["interactive", "staged", "unstaged", "worktree"])]
+        per_commit: bool,
+
+        /// Show how many tokens the review would use and what it would cost, without sending it
+        #[arg(long, conflicts_with_all = ["interactive", "per_commit", "messages"])]
+        estimate: bool,
+
+        /// Only review what was committed since the last review of this branch, carrying over the
+        /// earlier comments on lines that haven't changed
+        #[arg(long, conflicts_with_all = ["per_commit", "against", "staged", "unstaged", "worktree"])]
+        since_last: bool,
+
+        /// Also check the messages of the commits since the base revision (see `review-messages`)
+        #[arg(long, conflicts_with_all = ["staged", "unstaged", "worktree"])]
+        messages: bool,
+
+        #[command(flatten)]
+        diff: DiffArgs,
+    },
+    /// Write a pull request description for the committed changes
+    Describe {
+        /// Custom system prompt for the AI
+        #[arg(short, long)]
+        prompt: Option<String>,
+
         /// Specify a git commit to diff against (instead of using merge-base)
         #[arg(long)]
         against: Option<String>,
+
+        /// Fill in a pull request template section by section [default: the repository's template, e.g. .github/pull_request_template.md]
+        #[arg(long, num_args = 0..=1)]
+        template: Option<Option<std::path::PathBuf>>,
+
+        /// Write the description to a file instead of printing it
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Check the messages of the commits since the base revision against the configured conventions
+    ReviewMessages {
+        /// Specify a git commit to start from (instead of using merge-base)
+        #[arg(long)]
+        against: Option<String>,
+
+        /// Output format for the results
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
     },
     /// Show the diff that would be reviewed
     ShowDiff {
+        #[command(flatten)]
+        diff: DiffArgs,
+    },
+    /// Review the committed changes and post the comments as a review on a GitHub pull request
+    GithubPost {
+        /// Custom system prompt for the AI
+        #[arg(short, long)]
+        prompt: Option<String>,
+
+        /// Specify a git commit to diff against (instead of using merge-base)
+        #[arg(long)]
+        against: Option<String>,
+
+        /// Pull request number [default: the open pull request for the current branch]
+        #[arg(long)]
+        pr: Option<u64>,
+
+        /// Repository as owner/name [default: parsed from the remote's URL]
+        #[arg(long)]
+        repo: Option<String>,
+
+        /// Git remote to find the repository from
+        #[arg(long, default_value = "origin")]
+        remote: String,
+
+        /// Base URL of the GitHub REST API (e.g. https://github.example.com/api/v3 for GitHub Enterprise)
+        #[arg(long, env = "GITHUB_API_URL", default_value = github::DEFAULT_API_URL)]
+        api_url: String,
+
+        /// GitHub token with permission to review pull requests
+        #[arg(long, env = "GITHUB_TOKEN", hide_env_values = true)]
+        token: String,
+    },
+    /// Review the committed changes and post the comments as discussions on a GitLab merge request
+    GitlabPost {
+        /// Custom system prompt for the AI
+        #[arg(short, long)]
+        prompt: Option<String>,
+
         /// Specify a git commit to diff against (instead of using merge-base)
         #[arg(long)]
         against: Option<String>,
+
+        /// Merge request IID [default: the open merge request for the current branch]
+        #[arg(long)]
+        mr: Option<u64>,
+
+        /// Project path (group/project) or ID [default: parsed from the remote's URL]
+        #[arg(long)]
+        project: Option<String>,
+
+        /// Git remote to find the project from
+        #[arg(long, default_value = "origin")]
+        remote: String,
+
+        /// URL of the GitLab instance
+        #[arg(long, env = "CI_SERVER_URL", default_value = gitlab::DEFAULT_URL)]
+        url: String,
+
+        /// Project or personal access token with the `api` scope
+        #[arg(long, env = "GITLAB_TOKEN", hide_env_values = true)]
+        token: String,
+    },
+    /// Show the fixes suggested by a review, and apply them to the working tree
+    Fix {
+        /// Custom system prompt for the AI
+        #[arg(short, long)]
+        prompt: Option<String>,
+
+        /// Take the comments from a review saved with `--format json`, instead of reviewing the changes
+        #[arg(long, conflicts_with_all = ["prompt", "against", "staged", "unstaged", "worktree"])]
+        input: Option<std::path::PathBuf>,
+
+        /// Apply every fix without asking
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Only show the fixes
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Write the fixes to a patch file instead of changing the working tree
+        #[arg(long, conflicts_with = "dry_run")]
+        patch: Option<std::path::PathBuf>,
+
+        #[command(flatten)]
+        diff: DiffArgs,
+    },
+    /// Manage the baseline of comments that should not be reported again
+    Baseline {
+        #[command(subcommand)]
+        action: BaselineAction,
+    },
+    /// Manage a git hook that reviews changes before they are pushed or committed
+    Hook {
+        #[command(subcommand)]
+        action: HookAction,
+    },
+    /// Summarize the tokens and money b4sam has spent, from the ledger in the user data directory
+    Usage {
+        /// How to group the runs
+        #[arg(long, value_enum, default_value_t)]
+        by: report::GroupBy,
+
+        /// Only count runs from the last this many days
+        #[arg(long)]
+        days: Option<u64>,
+
+        /// Print CSV, e.g. for a spreadsheet
+        #[arg(long)]
+        csv: bool,
     },
+    /// Manage the cache of reviews of unchanged diff chunks
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum HookAction {
+    /// Install the hook, chaining to any existing hook
+    Install {
+        /// Which hook to install
+        #[arg(long, value_enum, default_value_t)]
+        hook: hook::Hook,
+
+        /// Whether to abort on comments at or above `--fail-on` [default threshold: error], or only show them
+        #[arg(long, value_enum, default_value_t)]
+        mode: hook::HookMode,
+    },
+    /// Remove the hook, restoring any hook it was chained to
+    Uninstall {
+        /// Which hook to remove
+        #[arg(long, value_enum, default_value_t)]
+        hook: hook::Hook,
+    },
+    /// Show which hooks are installed
+    Status,
+}
+
+#[derive(Subcommand)]
+enum CacheAction {
+    /// Delete every cached review
+    Clear,
+    /// Show how many reviews are cached and how much space they take
+    Stats,
+}
+
+#[derive(Subcommand)]
+enum BaselineAction {
+    /// Add the comments of a saved review, or the ones you dismiss in the terminal UI, to the
+    /// baseline, so they are not reported again
+    Update {
+        /// Custom system prompt for the AI
+        #[arg(short, long)]
+        prompt: Option<String>,
+
+        /// Take the comments from a review saved with `--format json`, instead of reviewing the changes
+        #[arg(long, conflicts_with_all = ["prompt", "against", "staged", "unstaged", "worktree"])]
+        input: Option<std::path::PathBuf>,
+
+        /// Only add the comments you dismiss or mark as false positives in the terminal UI
+        #[arg(short, long)]
+        interactive: bool,
+
+        /// Also add the error-severity comments (`Issue` and `LeftoverDebug`) of the saved review
+        #[arg(long, conflicts_with = "interactive")]
+        include_errors: bool,
+
+        #[command(flatten)]
+        diff: DiffArgs,
+    },
+}
+
+#[derive(clap::Args)]
+struct DiffArgs {
+    /// Specify a git commit to diff against (instead of using merge-base)
+    #[arg(long)]
+    against: Option<String>,
+
+    /// Review the changes staged in the index (against HEAD, or `--against` if given)
+    #[arg(long, group = "mode")]
+    staged: bool,
+
+    /// Review the changes in the working tree that have not been staged, including untracked files
+    #[arg(long, group = "mode", conflicts_with = "against")]
+    unstaged: bool,
+
+    /// Review every uncommitted change in the working tree, including untracked files
+    #[arg(long, group = "mode")]
+    worktree: bool,
+}
+
+impl DiffArgs {
+    fn source(&self) -> DiffSource {
+        let against = self.against.clone();
+        if self.staged {
+            DiffSource::Staged { against }
+        } else if self.unstaged {
+            DiffSource::Unstaged
+        } else if self.worktree {
+            DiffSource::Worktree { against }
+        } else {
+            DiffSource::Committed { against }
+        }
+    }
 }
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+async fn run() -> anyhow::Result<ExitCode> {
     let cli = Cli::parse();
+    let config = Config::load()?.merge(cli.config);
+
+    if let Some(Commands::Review {
+        messages: true,
+        format,
+        ..
+    }) = &cli.command
+    {
+        if *format != OutputFormat::Text {
+            anyhow::bail!("`--messages` only works with text output; use `b4sam review-messages` for other formats");
+        }
+    }
 
-    match cli.command {
-        Some(Commands::Review { prompt, against }) => {
-            review_code(prompt, cli.verbose, against.as_deref()).await?;
+    let mut message_findings = 0;
+    let review = match cli.command {
+        Some(Commands::Review {
+            prompt,
+            format,
+            interactive: _,
+            per_commit: true,
+            estimate: _,
+            since_last: _,
+            messages,
+            diff,
+        }) => {
+            let mut reviews = Vec::new();
+            for sha in changes::commits(diff.against.as_deref(), &config)? {
+                let changes = DiffSource::Commit(sha.clone()).changes(&config)?;
+                if changes.diff.trim().is_empty() {
+                    if cli.verbose {
+                        eprintln!("Skipping {}, which has no changes to review", sha);
+                    }
+                    continue;
+                }
+                if cli.verbose {
+                    eprintln!("Reviewing {}...", sha);
+                }
+                reviews.push(review_code(prompt.clone(), cli.verbose, &changes, &config).await?);
+            }
+            if format == OutputFormat::Sarif {
+                move_to_head(&mut reviews, &config)?;
+            }
+            output::print_commit_reviews(&reviews, format)?;
+            // the exit code follows the comments that were printed
+            let review = Review {
+                comments: reviews
+                    .iter()
+                    .flat_map(|(review, _)| review.comments.iter().cloned())
+                    .collect(),
+            };
+            if messages {
+                message_findings =
+                    check_messages(diff.against.as_deref(), format, &config, cli.verbose).await?;
+            }
+            review
+        }
+        Some(Commands::Review {
+            prompt,
+            format,
+            interactive,
+            per_commit: false,
+            estimate,
+            since_last,
+            messages,
+            diff,
+        }) => {
+            let last = if since_last {
+                let last = history::last_review()?;
+                if last.rewritten {
+                    eprintln!(
+                        "{} was rewritten since its last review, so the changes since then may include changes from a rebase",
+                        history::current_branch().unwrap_or_default()
+                    );
+                }
+                Some(last)
+            } else {
+                None
+            };
+            let source = match &last {
+                Some(last) => DiffSource::Committed {
+                    against: Some(last.head.clone()),
+                },
+                None => diff.source(),
+            };
+            let changes = fetch_changes(&source, &config, cli.verbose)?;
+            if estimate {
+                print_estimate(&review::plan(prompt, &changes, &config)?, config.model());
+                return Ok(ExitCode::SUCCESS);
+            }
+            let (mut review, mut metadata) =
+                review_code(prompt, cli.verbose, &changes, &config).await?;
+            if let Some(last) = last {
+                let carried = history::carry_forward(last.comments, &diff::parse(&changes.diff));
+                metadata.carried_over = carried.len();
+                review.comments.extend(carried);
+            }
+            if interactive {
+                let decisions = tui::run(&review, &diff::parse(&changes.diff))?;
+                if decisions.iter().any(|d| !d.is_remaining()) {
+                    let (added, path) = update_baseline(&review.comments, &decisions)?;
+                    eprintln!("Added {} dismissed comment(s) to {}", added, path.display());
+                }
+                let mut decisions = decisions.into_iter();
+                review
+                    .comments
+                    .retain(|_| decisions.next().is_none_or(|d| d.is_remaining()));
+            }
+            print_review(&review, &metadata, format)?;
+            if let DiffSource::Committed { against } = source {
+                history::record(&review.comments)?;
+                if messages {
+                    message_findings =
+                        check_messages(against.as_deref(), format, &config, cli.verbose).await?;
+                }
+            }
+            review
+        }
+        Some(Commands::ReviewMessages { against, format }) => {
+            message_findings =
+                check_messages(against.as_deref(), format, &config, cli.verbose).await?;
+            Review {
+                comments: Vec::new(),
+            }
+        }
+        Some(Commands::Describe {
+            prompt,
+            against,
+            template,
+            output,
+        }) => {
+            let template = match template {
+                Some(Some(path)) => Some(path),
+                Some(None) => Some(
+                    describe::find_template()
+                        .context("No pull request template found in the repository")?,
+                ),
+                None => None,
+            };
+            let changes = fetch_changes(&DiffSource::Committed { against }, &config, cli.verbose)?;
+
+            let started = std::time::Instant::now();
+            let reviewer = reviewer::reviewer(&config)?;
+            let markdown = describe::describe(
+                reviewer.as_ref(),
+                prompt,
+                &changes,
+                template.as_deref(),
+                &config,
+            )
+            .await;
+            // failed runs can still have spent money
+            record_usage(reviewer.as_ref(), started);
+            let markdown = markdown?;
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, markdown)
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                    println!("Wrote the description to {}", path.display());
+                }
+                None => print!("{}", markdown),
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Commands::ShowDiff { diff }) => {
+            let changes = diff.source().changes(&config)?;
+            println!("{}", changes.diff);
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Commands::GithubPost {
+            prompt,
+            against,
+            pr,
+            repo,
+            remote,
+            api_url,
+            token,
+        }) => {
+            let pull_request =
+                github::PullRequest::find(&api_url, token, repo.as_deref(), &remote, pr).await?;
+            let changes = fetch_changes(&DiffSource::Committed { against }, &config, cli.verbose)?;
+            let (review, metadata) = review_code(prompt, cli.verbose, &changes, &config).await?;
+            print_review(&review, &metadata, OutputFormat::Text)?;
+
+            let url = pull_request
+                .post_review(&review, &metadata, &diff::parse(&changes.diff))
+                .await?;
+            println!(
+                "Posted review on {}/{}#{}{}",
+                pull_request.owner,
+                pull_request.repo,
+                pull_request.number,
+                url.map(|url| format!(": {}", url)).unwrap_or_default()
+            );
+            review
+        }
+        Some(Commands::GitlabPost {
+            prompt,
+            against,
+            mr,
+            project,
+            remote,
+            url,
+            token,
+        }) => {
+            let merge_request =
+                gitlab::MergeRequest::find(&url, token, project.as_deref(), &remote, mr).await?;
+            let changes = fetch_changes(&DiffSource::Committed { against }, &config, cli.verbose)?;
+            let (review, metadata) = review_code(prompt, cli.verbose, &changes, &config).await?;
+            print_review(&review, &metadata, OutputFormat::Text)?;
+
+            let base = changes
+                .base
+                .as_deref()
+                .context("Committed changes always have a base")?;
+            let rejected = merge_request
+                .post_review(
+                    &review,
+                    &metadata,
+                    &diff::parse(&changes.diff),
+                    &gitlab::DiffRefs::new(base)?,
+                )
+                .await?;
+            for rejection in rejected {
+                eprintln!("{}", rejection);
+            }
+            println!(
+                "Posted review on {}!{}",
+                merge_request.project, merge_request.iid
+            );
+            review
+        }
+        Some(Commands::Fix {
+            prompt,
+            input,
+            yes,
+            dry_run,
+            patch,
+            diff,
+        }) => {
+            let review = match input {
+                Some(input) => read_review(&input)?,
+                None => {
+                    let changes = fetch_changes(&diff.source(), &config, cli.verbose)?;
+                    review_code(prompt, cli.verbose, &changes, &config).await?.0
+                }
+            };
+            apply::run(&review, yes, dry_run, patch.as_deref())?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Commands::Baseline {
+            action:
+                BaselineAction::Update {
+                    prompt,
+                    input,
+                    interactive,
+                    include_errors,
+                    diff,
+                },
+        }) => {
+            // a new review would differ from the one the user saw, so only add comments they chose
+            let (review, files) = match input {
+                Some(input) => (read_review(&input)?, Vec::new()),
+                None if interactive => {
+                    let changes = fetch_changes(&diff.source(), &config, cli.verbose)?;
+                    let (review, _) = review_code(prompt, cli.verbose, &changes, &config).await?;
+                    (review, diff::parse(&changes.diff))
+                }
+                None => anyhow::bail!(
+                    "Pass `--input` with a review saved with `--format json`, or `--interactive` to choose the comments to add"
+                ),
+            };
+            let decisions = if interactive {
+                tui::run(&review, &files)?
+            } else {
+                review
+                    .comments
+                    .iter()
+                    .map(|comment| {
+                        if include_errors || comment.comment_type.severity() < Severity::Error {
+                            tui::Decision::Dismissed
+                        } else {
+                            tui::Decision::Undecided
+                        }
+                    })
+                    .collect()
+            };
+
+            let (added, path) = update_baseline(&review.comments, &decisions)?;
+            println!("Added {} comment(s) to {}", added, path.display());
+            let left_out = decisions.iter().filter(|d| d.is_remaining()).count();
+            if !interactive && left_out > 0 {
+                println!(
+                    "Left out {} error-severity comment(s); pass `--include-errors` to add them too",
+                    left_out
+                );
+            }
+            return Ok(ExitCode::SUCCESS);
         }
-        Some(Commands::ShowDiff { against }) => {
-            let changes = get_changes(against.as_deref())?;
-            println!("{}", changes);
+        Some(Commands::Hook { action }) => {
+            match action {
+                HookAction::Install { hook, mode } => {
+                    hook::install(hook, mode, config.fail_on.unwrap_or(Severity::Error))?
+                }
+                HookAction::Uninstall { hook } => hook::uninstall(hook)?,
+                HookAction::Status => hook::status()?,
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Commands::Usage { by, days, csv }) => {
+            report::run(by, days, csv)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Commands::Cache { action }) => {
+            let cache = cache::Cache::open().context("No cache directory on this platform")?;
+            match action {
+                CacheAction::Clear => {
+                    let entries = cache.clear()?;
+                    println!("Removed {} cached review(s)", entries);
+                }
+                CacheAction::Stats => {
+                    let stats = cache.stats()?;
+                    println!(
+                        "{} cached review(s), {:.1} KiB, in {}",
+                        stats.entries,
+                        stats.bytes as f64 / 1024.0,
+                        stats.dir.display()
+                    );
+                }
+            }
+            return Ok(ExitCode::SUCCESS);
         }
         None => {
             // Default to review if no command is specified
-            review_code(None, cli.verbose, None).await?;
+            let changes = fetch_changes(
+                &DiffSource::Committed { against: None },
+                &config,
+                cli.verbose,
+            )?;
+            let (review, metadata) = review_code(None, cli.verbose, &changes, &config).await?;
+            print_review(&review, &metadata, OutputFormat::Text)?;
+            history::record(&review.comments)?;
+            review
         }
-    }
+    };
 
-    Ok(())
+    Ok(exit_code(&review, message_findings, config.fail_on))
 }
 
-async fn review_code(
-    custom_prompt: Option<String>,
-    verbose: bool,
+/// Checks and prints the messages of the commits since the base revision, returning the number
+/// of findings
+async fn check_messages(
     against: Option<&str>,
-) -> anyhow::Result<()> {
-    let default_prompt = r#"You are a helpful assistant that reviews code. The types of responses you can leave are "Nitpick", "LeftoverDebug", "UnnecessaryComment", "StyleIssue", "Question", "Issue", "Suggestion", "Idea". Also, redisplay the line of code that you are commenting on and tell the user where that line is in the file. Keep in mind that you will not see the entire file, only a diff that shows the sections that changed. This means that you may see variables and functions being used without seeing where they are defined. You are being invoked on code that compiles and passes all tests (you are simply a last pass sanity check).
-
-Nitpick: Small style issues, small issues in performance (e.g. cloning a vector when passing by reference would work).
-LeftoverDebug: Debug statements, println! statements, etc. that were probably left in by mistake.
-UnnecessaryComment: Comments that are not needed, or explain something overly-obvious. Be very strict about this. Comments that explain what the code does are not needed. The only comments that are needed are ones provided as documentation for public parts of the API, and those that explain *why* the code is the way it is (rather than what it does).
-StyleIssue: Style issues that do not fall under the other categories.
-Question: Questions about the code, or questions that the user should answer before merging (e.g. have you updated the docs?).
-Issue: Issues with the code that are not style related.
-Suggestion: Suggestions for improvements.
-Idea: Ideas for improvements.
+    format: OutputFormat,
+    config: &Config,
+    verbose: bool,
+) -> anyhow::Result<usize> {
+    let mut commits = Vec::new();
+    for sha in changes::commits(against, config)? {
+        let changes = DiffSource::Commit(sha).changes(config)?;
+        let commit = changes
+            .commit
+            .context("Commit changes always have a commit")?;
+        commits.push((commit, changes.diff));
+    }
 
-Remember, the code you are reviewing has already been compiled without errors and passed all tests. There is no possibility that the code would not compile, and there are no errors in the code that would prevent it from compiling."#;
+    let started = std::time::Instant::now();
+    let reviewer = if config
+        .message_rules()
+        .contains(&messages::MessageRule::MatchesDiff)
+    {
+        Some(reviewer::reviewer(config)?)
+    } else {
+        None
+    };
+    let reviews = messages::review_messages(&commits, reviewer.as_deref(), config, verbose).await;
+    if let Some(reviewer) = &reviewer {
+        record_usage(reviewer.as_ref(), started);
+    }
+    let reviews = reviews?;
+    output::print_message_reviews(&reviews, format)?;
 
-    let system_prompt = custom_prompt.unwrap_or_else(|| default_prompt.to_string());
-    let client = ChatClient::from_env("o3")?;
+    Ok(reviews.iter().map(|review| review.findings.len()).sum())
+}
 
+fn fetch_changes(source: &DiffSource, config: &Config, verbose: bool) -> anyhow::Result<Changes> {
     if verbose {
         eprintln!("Fetching changes against default branch...");
     }
 
-    let changes = get_changes(against)?;
+    source.changes(config)
+}
 
-    if verbose {
-        eprintln!("Sending changes to AI for review...");
+/// Moves the comments of each commit's review to where their lines are in `HEAD`, which is what
+/// code scanning shows results on, dropping those whose lines have changed since
+fn move_to_head(reviews: &mut [(Review, RunMetadata)], config: &Config) -> anyhow::Result<()> {
+    for (review, metadata) in reviews {
+        let Some(commit) = &metadata.commit else {
+            continue;
+        };
+        let comments = std::mem::take(&mut review.comments);
+        review.comments = history::move_to_head(comments, &commit.sha, config)?;
     }
+    Ok(())
+}
 
-    let review: Review = client
-        .chat_with_system_prompt(&system_prompt, &changes)
-        .await?;
-
-    // Display usage information
-    let cost = client.cost().unwrap_or(0.0);
-
-    println!("Code Review Results [${:.2}]", cost);
-    println!("===================\n");
-
-    for comment in review.comments {
-        let color = match comment.comment_type {
-            CommentType::Nitpick => "\x1b[38;5;208m",          // Orange
-            CommentType::LeftoverDebug => "\x1b[38;5;9m",      // Bright Red
-            CommentType::UnnecessaryComment => "\x1b[38;5;8m", // Gray
-            CommentType::StyleIssue => "\x1b[38;5;226m",       // Yellow
-            CommentType::Question => "\x1b[38;5;39m",          // Blue
-            CommentType::Issue => "\x1b[38;5;196m",            // Red
-            CommentType::Suggestion => "\x1b[38;5;34m",        // Green
-            CommentType::Idea => "\x1b[38;5;141m",             // Purple
-        };
-        let reset = "\x1b[0m";
+/// Reads a review saved with `--format json`
+fn read_review(path: &std::path::Path) -> anyhow::Result<Review> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("Invalid review in {}", path.display()))
+}
 
-        let comment_type = format!("{}", comment.comment_type);
-        println!("{}[{}]{} in: {}", color, comment_type, reset, comment.r#in);
+/// Adds the comments that were dismissed or marked as false positives to the baseline, returning
+/// how many were not in it yet and where it is
+fn update_baseline(
+    comments: &[Comment],
+    decisions: &[tui::Decision],
+) -> anyhow::Result<(usize, std::path::PathBuf)> {
+    let mut baseline = baseline::Baseline::load()?;
+    let mut added = 0;
+    for (comment, decision) in comments.iter().zip(decisions) {
+        if !decision.is_remaining() && baseline.add(comment) {
+            added += 1;
+        }
+    }
+    Ok((added, baseline.save()?))
+}
+
+/// Commit message findings count as warnings
+fn exit_code(review: &Review, message_findings: usize, fail_on: Option<Severity>) -> ExitCode {
+    let failed = fail_on.is_some_and(|threshold| {
+        review
+            .comments
+            .iter()
+            .any(|comment| comment.comment_type.severity() >= threshold)
+            || (message_findings > 0 && Severity::Warning >= threshold)
+    });
+
+    if failed {
+        ExitCode::from(EXIT_FINDINGS)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn print_estimate(plan: &ReviewPlan, model: &str) {
+    let estimate = &plan.estimate;
+    match estimate.cost {
+        Some(cost) => println!("Estimated cost with {}: ${:.2}", model, cost),
+        None => println!(
+            "Estimated cost with {}: unknown (no price for this model; set it with `--price`)",
+            model
+        ),
+    }
+    println!(
+        "{} request(s), about {} prompt and {} completion tokens",
+        estimate.requests, estimate.prompt_tokens, estimate.completion_tokens
+    );
+    if plan.cached > 0 {
+        println!("{} chunk(s) already reviewed, from the cache", plan.cached);
+    }
+    if !plan.skipped_files.is_empty() {
+        println!(
+            "Skipped (larger than the token budget): {}",
+            plan.skipped_files.join(", ")
+        );
+    }
+    if !plan.trimmed_files.is_empty() {
         println!(
-            "{}line: {}",
-            " ".repeat(comment_type.len() + 1),
-            comment.line.trim()
+            "Skipped (to stay within the maximum cost): {}",
+            plan.trimmed_files.join(", ")
         );
-        println!("{}{}{}\n", color, comment.comment, reset);
     }
+}
 
-    Ok(())
+/// Reviews the changes with a new reviewer, recording its usage in the ledger
+async fn review_code(
+    custom_prompt: Option<String>,
+    verbose: bool,
+    changes: &Changes,
+    config: &Config,
+) -> anyhow::Result<(Review, RunMetadata)> {
+    let started = std::time::Instant::now();
+    let reviewer = reviewer::reviewer(config)?;
+    let baseline = baseline::Baseline::load()?;
+    let result = reviewer
+        .review_changes(custom_prompt, changes, config, Some(&baseline), verbose)
+        .await;
+    // failed runs can still have spent money
+    record_usage(reviewer.as_ref(), started);
+    let (review, metadata) = result?;
+    for warning in &metadata.warnings {
+        eprintln!("{}", warning);
+    }
+    Ok((review, metadata))
+}
+
+/// Records the reviewer's usage in the ledger, only warning if that fails
+fn record_usage(reviewer: &dyn reviewer::Reviewer, started: std::time::Instant) {
+    if let Err(e) = usage::record(reviewer, started) {
+        eprintln!("Failed to record the run's usage: {:#}", e);
+    }
 }
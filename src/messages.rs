@@ -0,0 +1,316 @@
+use futures::{StreamExt, TryStreamExt};
+
+use crate::chunk::truncate;
+use crate::config::Config;
+use crate::reviewer::Reviewer;
+use crate::Commit;
+
+/// A convention commit messages are checked against
+#[derive(
+    serde::Serialize, serde::Deserialize, clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum MessageRule {
+    /// The subject follows Conventional Commits, like `fix(parser): handle empty input`
+    ConventionalCommits,
+    /// The subject is at most `max_subject_length` characters long
+    SubjectLength,
+    /// The subject starts with a verb in the imperative mood ("Add", not "Added" or "Adds")
+    ImperativeMood,
+    /// The message references an issue, like `#123` or `PROJ-123`
+    IssueReference,
+    /// The model agrees that the message describes the changes
+    MatchesDiff,
+}
+
+impl MessageRule {
+    pub const DEFAULT: [MessageRule; 3] = [
+        MessageRule::SubjectLength,
+        MessageRule::ImperativeMood,
+        MessageRule::MatchesDiff,
+    ];
+}
+
+/// A way a commit message breaks a convention
+#[derive(serde::Serialize, Debug)]
+pub struct Finding {
+    pub rule: MessageRule,
+    pub message: String,
+}
+
+/// The findings for one commit's message
+#[derive(serde::Serialize, Debug)]
+pub struct MessageReview {
+    #[serde(flatten)]
+    pub commit: Commit,
+    pub findings: Vec<Finding>,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+struct MessageCheck {
+    /// Whether the message is an accurate summary of the changes
+    matches: bool,
+    /// What the message gets wrong or leaves out, if it does not match
+    explanation: String,
+}
+
+const MATCHES_DIFF_PROMPT: &str = "You check commit messages. Given a commit message and the commit's diff, decide whether the message accurately summarizes what the changes do. Minor omissions are fine; flag messages that describe different changes, miss the main change, or are too vague to be useful (like \"fix\" or \"wip\").";
+
+const CONVENTIONAL_TYPES: [&str; 11] = [
+    "build", "chore", "ci", "docs", "feat", "fix", "perf", "refactor", "revert", "style", "test",
+];
+
+/// Checks the message of each commit against the configured rules. Only `matches-diff` needs the
+/// model, and it is sent one commit at a time; the rule is skipped without a `reviewer`.
+pub async fn review_messages(
+    commits: &[(Commit, String)],
+    reviewer: Option<&dyn Reviewer>,
+    config: &Config,
+    verbose: bool,
+) -> anyhow::Result<Vec<MessageReview>> {
+    let rules = config.message_rules();
+    let reviewer = reviewer.filter(|_| rules.contains(&MessageRule::MatchesDiff));
+    if verbose && reviewer.is_some() {
+        eprintln!(
+            "Checking {} commit message(s) against their changes...",
+            commits.len()
+        );
+    }
+
+    futures::stream::iter(commits)
+        .map(|(commit, diff)| async move {
+            let mut findings = lint(&commit.message, rules, config.max_subject_length());
+            if let Some(reviewer) = reviewer {
+                if let Some(finding) = check_matches_diff(reviewer, commit, diff, config).await? {
+                    findings.push(finding);
+                }
+            }
+            anyhow::Ok(MessageReview {
+                commit: commit.clone(),
+                findings,
+            })
+        })
+        .buffered(config.concurrency())
+        .try_collect()
+        .await
+}
+
+async fn check_matches_diff(
+    reviewer: &dyn Reviewer,
+    commit: &Commit,
+    diff: &str,
+    config: &Config,
+) -> anyhow::Result<Option<Finding>> {
+    let diff = truncate(diff, config.chunk_tokens());
+    let prompt = format!("Commit message:\n\n{}\n\nDiff:\n\n{}", commit.message, diff);
+    let check: MessageCheck = reviewer.chat(MATCHES_DIFF_PROMPT, &prompt).await?;
+
+    Ok((!check.matches).then_some(Finding {
+        rule: MessageRule::MatchesDiff,
+        message: check.explanation,
+    }))
+}
+
+/// Applies the rules that don't need the model
+pub fn lint(message: &str, rules: &[MessageRule], max_subject_length: usize) -> Vec<Finding> {
+    let subject = message.lines().next().unwrap_or_default().trim();
+    let mut findings = Vec::new();
+    let mut add = |rule, message: String| findings.push(Finding { rule, message });
+
+    for rule in rules {
+        match rule {
+            MessageRule::ConventionalCommits => {
+                if conventional_description(subject).is_none() {
+                    add(
+                        *rule,
+                        "The subject does not follow Conventional Commits (`type(scope): description`)"
+                            .to_string(),
+                    );
+                }
+            }
+            MessageRule::SubjectLength => {
+                let length = subject.chars().count();
+                if length > max_subject_length {
+                    add(
+                        *rule,
+                        format!(
+                            "The subject is {} characters long; keep it to {} at most",
+                            length, max_subject_length
+                        ),
+                    );
+                }
+            }
+            MessageRule::ImperativeMood => {
+                let description = conventional_description(subject).unwrap_or(subject);
+                if let Some(word) = first_word(description).filter(|w| !is_imperative(w)) {
+                    add(
+                        *rule,
+                        format!(
+                            "The subject should start with a verb in the imperative mood, not \"{}\" (e.g. \"Add\", not \"Added\" or \"Adds\")",
+                            word
+                        ),
+                    );
+                }
+            }
+            MessageRule::IssueReference => {
+                if !message.split_whitespace().any(is_issue_reference) {
+                    add(
+                        *rule,
+                        "The message does not reference an issue (like #123 or PROJ-123)"
+                            .to_string(),
+                    );
+                }
+            }
+            // checked by the model
+            MessageRule::MatchesDiff => {}
+        }
+    }
+
+    findings
+}
+
+/// The description of a Conventional Commits subject like `feat(scope)!: description`
+fn conventional_description(subject: &str) -> Option<&str> {
+    let (head, description) = subject.split_once(": ")?;
+    let head = head.strip_suffix('!').unwrap_or(head);
+    let kind = match head.split_once('(') {
+        Some((kind, scope)) => {
+            scope.strip_suffix(')').filter(|scope| !scope.is_empty())?;
+            kind
+        }
+        None => head,
+    };
+
+    (CONVENTIONAL_TYPES.contains(&kind) && !description.trim().is_empty())
+        .then_some(description.trim())
+}
+
+/// The first word of a subject, skipping tags like `[ui]` or `JIRA-12:`
+fn first_word(description: &str) -> Option<&str> {
+    description
+        .split_whitespace()
+        .find(|word| !(word.starts_with('[') || word.ends_with(':') || is_issue_reference(word)))
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|word| word.chars().all(char::is_alphabetic) && !word.is_empty())
+}
+
+/// A rough check for past tense ("Added"), gerunds ("Adding") and third person ("Adds")
+fn is_imperative(word: &str) -> bool {
+    const EXCEPTIONS: [&str; 14] = [
+        "bring", "embed", "exceed", "feed", "need", "proceed", "seed", "shed", "speed", "string",
+        "succeed", "focus", "process", "alias",
+    ];
+
+    let word = word.to_lowercase();
+    if EXCEPTIONS.contains(&word.as_str()) {
+        return true;
+    }
+
+    !(word.ends_with("ed")
+        || word.ends_with("ing")
+        || (word.ends_with('s') && !word.ends_with("ss") && !word.ends_with("us")))
+}
+
+fn is_issue_reference(word: &str) -> bool {
+    let word = word.trim_matches(|c: char| matches!(c, '(' | ')' | '[' | ']' | ',' | '.' | ':'));
+    if word.contains("/issues/") || word.contains("/pull/") || word.contains("/merge_requests/") {
+        return true;
+    }
+    // other links, whose fragments can look like issue numbers
+    if word.contains("://") {
+        return false;
+    }
+
+    // #123, owner/repo#123 or PROJ-123
+    if let Some((_, number)) = word.rsplit_once('#') {
+        return !number.is_empty() && number.chars().all(|c| c.is_ascii_digit());
+    }
+    match word.split_once('-') {
+        Some((project, number)) => {
+            project.len() >= 2
+                && project.starts_with(|c: char| c.is_ascii_uppercase())
+                && project
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+                && !number.is_empty()
+                && number.chars().all(|c| c.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules_broken(message: &str, rules: &[MessageRule]) -> Vec<MessageRule> {
+        lint(message, rules, 20)
+            .into_iter()
+            .map(|finding| finding.rule)
+            .collect()
+    }
+
+    #[test]
+    fn subjects_should_be_imperative() {
+        for word in ["Added", "Adds", "Adding"] {
+            assert!(!is_imperative(word), "{}", word);
+        }
+        for word in ["Add", "Process", "Focus", "Embed", "Address"] {
+            assert!(is_imperative(word), "{}", word);
+        }
+
+        let rules = [MessageRule::ImperativeMood];
+        assert!(rules_broken("Add a flag", &rules).is_empty());
+        assert_eq!(rules_broken("Added a flag", &rules), rules);
+        // tags and conventional prefixes are skipped
+        assert_eq!(rules_broken("[cli] Adds a flag", &rules), rules);
+        assert_eq!(rules_broken("feat(cli): adding a flag", &rules), rules);
+        assert!(rules_broken("PROJ-12: Add a flag", &rules).is_empty());
+    }
+
+    #[test]
+    fn conventional_subjects_have_a_type_and_a_description() {
+        assert_eq!(conventional_description("feat: add x"), Some("add x"));
+        assert_eq!(conventional_description("feat(cli)!: add x"), Some("add x"));
+        assert_eq!(conventional_description("fix!: x"), Some("x"));
+        assert_eq!(conventional_description("feat(): x"), None);
+        assert_eq!(conventional_description("feat: "), None);
+        assert_eq!(conventional_description("feat:"), None);
+        assert_eq!(conventional_description("feature: x"), None);
+        assert_eq!(conventional_description("Add x"), None);
+    }
+
+    #[test]
+    fn issue_references() {
+        for word in ["#123", "PROJ-123", "owner/repo#1", "(#12).", "AB2-7:"] {
+            assert!(is_issue_reference(word), "{}", word);
+        }
+        assert!(is_issue_reference("https://github.com/o/r/issues/1"));
+        for word in [
+            "#",
+            "#abc",
+            "P-1",
+            "proj-1",
+            "PROJ-",
+            "https://example.com/page#1",
+            "https://example.com/docs",
+        ] {
+            assert!(!is_issue_reference(word), "{}", word);
+        }
+
+        let rules = [MessageRule::IssueReference];
+        assert!(rules_broken("Fix a crash\n\nFixes #12", &rules).is_empty());
+        assert_eq!(rules_broken("Fix a crash", &rules), rules);
+    }
+
+    #[test]
+    fn subjects_can_be_exactly_the_maximum_length() {
+        let rules = [MessageRule::SubjectLength];
+        let subject = "Add".to_string() + &"x".repeat(17);
+        assert_eq!(subject.len(), 20);
+        assert!(rules_broken(&subject, &rules).is_empty());
+        assert_eq!(rules_broken(&(subject + "x"), &rules), rules);
+        // only the first line is the subject
+        assert!(rules_broken(&format!("Add\n\n{}", "x".repeat(30)), &rules).is_empty());
+    }
+}
@@ -0,0 +1,276 @@
+use crate::messages::MessageReview;
+use crate::{Comment, CommentType, Commit, Review};
+
+/// How the review results are written to stdout
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Colored, human-readable text
+    #[default]
+    Text,
+    /// A single JSON document containing the run metadata and every comment
+    Json,
+    /// One JSON object per line: the run metadata first, then each comment
+    Ndjson,
+    /// A SARIF 2.1.0 log, e.g. for uploading to GitHub code scanning
+    Sarif,
+}
+
+/// Information about a review run that is reported alongside its comments
+#[derive(serde::Serialize, Debug)]
+pub struct RunMetadata {
+    pub model: String,
+    /// The revision the changes were diffed against (`None` when diffing the index against the working tree)
+    pub base: Option<String>,
+    /// The cost of the run in dollars, if it is known for the model
+    pub cost: Option<f64>,
+    /// Files that were not reviewed because they are larger than the token budget
+    pub skipped_files: Vec<String>,
+    /// Files that were not reviewed to keep the estimated cost under the maximum
+    pub trimmed_files: Vec<String>,
+    /// How many comments were hidden because they are in the baseline file
+    pub suppressed: usize,
+    /// How many comments were carried over from the last review, with `--since-last`
+    pub carried_over: usize,
+    /// The commit that was reviewed, with `--per-commit`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<Commit>,
+    /// Problems that didn't stop the run, like a review that could not be cached
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+struct JsonReport<'a> {
+    #[serde(flatten)]
+    metadata: &'a RunMetadata,
+    comments: &'a [Comment],
+}
+
+#[derive(serde::Serialize)]
+struct CommitsReport<'a> {
+    commits: Vec<JsonReport<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum NdjsonRecord<'a> {
+    Run(&'a RunMetadata),
+    Comment(&'a Comment),
+}
+
+pub fn print_review(
+    review: &Review,
+    metadata: &RunMetadata,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Text => print_text(review, metadata),
+        OutputFormat::Json => {
+            let report = JsonReport {
+                metadata,
+                comments: &review.comments,
+            };
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        OutputFormat::Ndjson => print_ndjson(review, metadata)?,
+        OutputFormat::Sarif => {
+            let log = crate::sarif::sarif_log(&[(review, metadata)]);
+            println!("{}", serde_json::to_string_pretty(&log)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the reviews of several commits, oldest first. JSON output is one document with a
+/// report per commit, and SARIF output is one log with a single run for all of them.
+pub fn print_commit_reviews(
+    reviews: &[(Review, RunMetadata)],
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Text => {
+            for (review, metadata) in reviews {
+                print_text(review, metadata);
+            }
+        }
+        OutputFormat::Json => {
+            let report = CommitsReport {
+                commits: reviews
+                    .iter()
+                    .map(|(review, metadata)| JsonReport {
+                        metadata,
+                        comments: &review.comments,
+                    })
+                    .collect(),
+            };
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        OutputFormat::Ndjson => {
+            for (review, metadata) in reviews {
+                print_ndjson(review, metadata)?;
+            }
+        }
+        OutputFormat::Sarif => {
+            let reviews: Vec<(&Review, &RunMetadata)> =
+                reviews.iter().map(|(r, m)| (r, m)).collect();
+            let log = crate::sarif::sarif_log(&reviews);
+            println!("{}", serde_json::to_string_pretty(&log)?);
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+struct MessagesReport<'a> {
+    commits: &'a [MessageReview],
+}
+
+#[derive(serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum MessageRecord<'a> {
+    MessageFinding {
+        #[serde(flatten)]
+        commit: &'a Commit,
+        #[serde(flatten)]
+        finding: &'a crate::messages::Finding,
+    },
+}
+
+/// Prints the findings for each commit message
+pub fn print_message_reviews(
+    reviews: &[MessageReview],
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Text => {
+            println!("Commit Message Results");
+            println!("======================\n");
+            for review in reviews.iter().filter(|r| !r.findings.is_empty()) {
+                println!(
+                    "\x1b[1mcommit {} {}\x1b[0m",
+                    review.commit.sha, review.commit.subject
+                );
+                for finding in &review.findings {
+                    let rule = serde_json::to_value(finding.rule)?;
+                    println!(
+                        "  \x1b[38;5;226m[{}]\x1b[0m {}",
+                        rule.as_str().unwrap_or_default(),
+                        finding.message
+                    );
+                }
+                println!();
+            }
+            if reviews.iter().all(|r| r.findings.is_empty()) {
+                println!("All {} commit message(s) look good\n", reviews.len());
+            }
+        }
+        OutputFormat::Json => {
+            let report = MessagesReport { commits: reviews };
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        OutputFormat::Ndjson => {
+            for review in reviews {
+                for finding in &review.findings {
+                    let record = MessageRecord::MessageFinding {
+                        commit: &review.commit,
+                        finding,
+                    };
+                    println!("{}", serde_json::to_string(&record)?);
+                }
+            }
+        }
+        OutputFormat::Sarif => {
+            anyhow::bail!("SARIF output is not supported for commit messages")
+        }
+    }
+
+    Ok(())
+}
+
+fn print_ndjson(review: &Review, metadata: &RunMetadata) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string(&NdjsonRecord::Run(metadata))?);
+    for comment in &review.comments {
+        println!(
+            "{}",
+            serde_json::to_string(&NdjsonRecord::Comment(comment))?
+        );
+    }
+    Ok(())
+}
+
+fn print_text(review: &Review, metadata: &RunMetadata) {
+    if let Some(commit) = &metadata.commit {
+        println!("\x1b[1mcommit {} {}\x1b[0m", commit.sha, commit.subject);
+    }
+    println!("Code Review Results [${:.2}]", metadata.cost.unwrap_or(0.0));
+    println!("===================\n");
+
+    if !metadata.skipped_files.is_empty() {
+        println!(
+            "Skipped (larger than the token budget): {}\n",
+            metadata.skipped_files.join(", ")
+        );
+    }
+    if !metadata.trimmed_files.is_empty() {
+        println!(
+            "Skipped (to stay within the maximum cost): {}\n",
+            metadata.trimmed_files.join(", ")
+        );
+    }
+    if metadata.suppressed > 0 {
+        println!(
+            "{} comment(s) suppressed by {}\n",
+            metadata.suppressed,
+            crate::baseline::BASELINE_FILE
+        );
+    }
+    if metadata.carried_over > 0 {
+        println!(
+            "{} comment(s) carried over from the last review\n",
+            metadata.carried_over
+        );
+    }
+
+    for comment in &review.comments {
+        let color = match comment.comment_type {
+            CommentType::Nitpick => "\x1b[38;5;208m",          // Orange
+            CommentType::LeftoverDebug => "\x1b[38;5;9m",      // Bright Red
+            CommentType::UnnecessaryComment => "\x1b[38;5;8m", // Gray
+            CommentType::StyleIssue => "\x1b[38;5;226m",       // Yellow
+            CommentType::Question => "\x1b[38;5;39m",          // Blue
+            CommentType::Issue => "\x1b[38;5;196m",            // Red
+            CommentType::Suggestion => "\x1b[38;5;34m",        // Green
+            CommentType::Idea => "\x1b[38;5;141m",             // Purple
+        };
+        let reset = "\x1b[0m";
+
+        let comment_type = format!("{}", comment.comment_type);
+        let unanchored = if comment.anchored {
+            ""
+        } else {
+            " (not found in the diff)"
+        };
+        let fix = if comment.fix.is_some() {
+            " (fix available)"
+        } else {
+            ""
+        };
+        println!(
+            "{}[{}]{} in: {}{}{}",
+            color,
+            comment_type,
+            reset,
+            comment.location(),
+            unanchored,
+            fix
+        );
+        println!(
+            "{}line: {}",
+            " ".repeat(comment_type.len() + 1),
+            comment.line.trim()
+        );
+        println!("{}{}{}\n", color, comment.comment, reset);
+    }
+}
@@ -0,0 +1,123 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use tysm::chat_completions::{ChatUsage, JsonSchemaFormat};
+
+use crate::reviewer::Reviewer;
+
+/// Setting this to a directory saves every response from the model there, as a fixture
+pub const RECORD_ENV: &str = "B4SAM_RECORD";
+/// Setting this to a directory of fixtures answers every request from them, without calling
+/// the model
+pub const REPLAY_ENV: &str = "B4SAM_REPLAY";
+
+/// A recorded request and the model's response to it
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Fixture {
+    model: String,
+    schema: String,
+    /// The user prompt, to make fixtures easier to tell apart; only the key is used for matching
+    prompt: String,
+    response: serde_json::Value,
+}
+
+/// Identifies a request by its prompts and schema. The model is left out, so fixtures recorded
+/// with one model can be replayed with any.
+fn key(system_prompt: &str, prompt: &str, schema: &JsonSchemaFormat) -> String {
+    let mut hasher = Sha256::new();
+    for part in [schema.name.as_str(), system_prompt, prompt] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+fn fixture_path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(format!("{}.json", key))
+}
+
+/// Passes requests on to another reviewer, saving each response as a fixture
+pub struct Recorder {
+    pub inner: Box<dyn Reviewer>,
+    pub dir: PathBuf,
+}
+
+#[async_trait::async_trait]
+impl Reviewer for Recorder {
+    async fn chat_json(
+        &self,
+        system_prompt: &str,
+        prompt: &str,
+        schema: &JsonSchemaFormat,
+    ) -> anyhow::Result<serde_json::Value> {
+        let response = self.inner.chat_json(system_prompt, prompt, schema).await?;
+
+        let fixture = Fixture {
+            model: self.inner.model().to_string(),
+            schema: schema.name.clone(),
+            prompt: prompt.to_string(),
+            response,
+        };
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let path = fixture_path(&self.dir, &key(system_prompt, prompt, schema));
+        std::fs::write(&path, serde_json::to_string_pretty(&fixture)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        Ok(fixture.response)
+    }
+
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    fn usage(&self) -> ChatUsage {
+        self.inner.usage()
+    }
+
+    fn cost(&self) -> Option<f64> {
+        self.inner.cost()
+    }
+}
+
+/// Answers requests from the fixtures saved by a [`Recorder`]
+pub struct Replayer {
+    pub dir: PathBuf,
+    pub model: String,
+}
+
+#[async_trait::async_trait]
+impl Reviewer for Replayer {
+    async fn chat_json(
+        &self,
+        system_prompt: &str,
+        prompt: &str,
+        schema: &JsonSchemaFormat,
+    ) -> anyhow::Result<serde_json::Value> {
+        let path = fixture_path(&self.dir, &key(system_prompt, prompt, schema));
+        let contents = std::fs::read_to_string(&path).with_context(|| {
+            format!(
+                "No recorded response for this request (expected {}); record one with {}",
+                path.display(),
+                RECORD_ENV
+            )
+        })?;
+        let fixture: Fixture = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        Ok(fixture.response)
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn usage(&self) -> ChatUsage {
+        ChatUsage::default()
+    }
+
+    fn cost(&self) -> Option<f64> {
+        None
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use b4sam::usage::{self, Entry};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// How `b4sam usage` groups the runs
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GroupBy {
+    /// One row per day (UTC)
+    #[default]
+    Day,
+    /// One row per week, starting on Monday (UTC)
+    Week,
+    /// One row per repository
+    Repo,
+    /// One row per run
+    Run,
+}
+
+/// The totals of a group of runs
+#[derive(Default)]
+struct Row {
+    runs: usize,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    cost: f64,
+    /// Runs whose cost isn't known, which are left out of `cost`
+    unpriced: usize,
+    duration_secs: f64,
+}
+
+impl Row {
+    fn add(&mut self, entry: &Entry) {
+        self.runs += 1;
+        self.prompt_tokens += entry.prompt_tokens as u64;
+        self.completion_tokens += entry.completion_tokens as u64;
+        match entry.cost {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced += 1,
+        }
+        self.duration_secs += entry.duration_secs;
+    }
+}
+
+/// Prints the ledger's runs from the last `days` days, grouped by `by`, as a table or CSV
+pub fn run(by: GroupBy, days: Option<u64>, csv: bool) -> anyhow::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let since = days.map_or(0, |days| now.saturating_sub(days * SECONDS_PER_DAY));
+    let entries: Vec<Entry> = usage::load()?
+        .into_iter()
+        .filter(|entry| entry.time >= since)
+        .collect();
+
+    let mut table: Vec<Vec<String>> = Vec::new();
+    if by == GroupBy::Run {
+        table.push(columns(&[
+            "time",
+            "model",
+            "repo",
+            "branch",
+            "prompt_tokens",
+            "completion_tokens",
+            "cost",
+            "duration_secs",
+        ]));
+        for entry in &entries {
+            table.push(vec![
+                date_time(entry.time),
+                entry.model.clone(),
+                entry.repo.clone().unwrap_or_default(),
+                entry.branch.clone().unwrap_or_default(),
+                entry.prompt_tokens.to_string(),
+                entry.completion_tokens.to_string(),
+                entry.cost.map(|c| format!("{:.4}", c)).unwrap_or_default(),
+                format!("{:.1}", entry.duration_secs),
+            ]);
+        }
+    } else {
+        let rows = group(&entries, by);
+        let heading = match by {
+            GroupBy::Week => "week",
+            GroupBy::Repo => "repo",
+            _ => "day",
+        };
+        table.push(columns(&[
+            heading,
+            "runs",
+            "prompt_tokens",
+            "completion_tokens",
+            "cost",
+            "unpriced_runs",
+            "duration_secs",
+        ]));
+        for (key, row) in rows {
+            table.push(vec![
+                key,
+                row.runs.to_string(),
+                row.prompt_tokens.to_string(),
+                row.completion_tokens.to_string(),
+                format!("{:.4}", row.cost),
+                row.unpriced.to_string(),
+                format!("{:.1}", row.duration_secs),
+            ]);
+        }
+    }
+
+    if csv {
+        for line in &table {
+            let fields: Vec<String> = line.iter().map(|field| csv_field(field)).collect();
+            println!("{}", fields.join(","));
+        }
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("No runs recorded");
+        return Ok(());
+    }
+    let widths: Vec<usize> = (0..table[0].len())
+        .map(|column| {
+            table
+                .iter()
+                .map(|line| line[column].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    for line in &table {
+        let cells: Vec<String> = line
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+
+    let total = entries.iter().fold(Row::default(), |mut total, entry| {
+        total.add(entry);
+        total
+    });
+    println!("\nTotal: ${:.2} over {} run(s)", total.cost, total.runs);
+    if total.unpriced > 0 {
+        println!(
+            "{} run(s) used models with unknown prices and are not counted",
+            total.unpriced
+        );
+    }
+    Ok(())
+}
+
+/// Totals the runs by day, week or repository
+fn group(entries: &[Entry], by: GroupBy) -> BTreeMap<String, Row> {
+    let mut rows: BTreeMap<String, Row> = BTreeMap::new();
+    for entry in entries {
+        let key = match by {
+            GroupBy::Week => {
+                // 1970-01-01 was a Thursday; the first days have no Monday before them
+                let day = entry.time / SECONDS_PER_DAY;
+                date(day.saturating_sub((day + 3) % 7) * SECONDS_PER_DAY)
+            }
+            GroupBy::Repo => entry.repo.clone().unwrap_or_else(|| "(none)".to_string()),
+            GroupBy::Day | GroupBy::Run => date(entry.time),
+        };
+        rows.entry(key).or_default().add(entry);
+    }
+    rows
+}
+
+fn columns(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// A Unix time as a UTC date like `2024-03-09`
+fn date(time: u64) -> String {
+    // Howard Hinnant's civil_from_days
+    let days = (time / SECONDS_PER_DAY) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// A Unix time as a UTC date and time like `2024-03-09 14:05:00`
+fn date_time(time: u64) -> String {
+    let seconds = time % SECONDS_PER_DAY;
+    format!(
+        "{} {:02}:{:02}:{:02}",
+        date(time),
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(time: u64, repo: Option<&str>, cost: Option<f64>) -> Entry {
+        Entry {
+            time,
+            model: "o3".to_string(),
+            prompt_tokens: 100,
+            completion_tokens: 10,
+            cost,
+            repo: repo.map(str::to_string),
+            branch: None,
+            duration_secs: 1.0,
+        }
+    }
+
+    #[test]
+    fn dates_are_utc() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date_time(SECONDS_PER_DAY - 1), "1970-01-01 23:59:59");
+        // leap days, including in a year divisible by 400
+        assert_eq!(date(11_016 * SECONDS_PER_DAY), "2000-02-29");
+        assert_eq!(date(11_017 * SECONDS_PER_DAY), "2000-03-01");
+        assert_eq!(date(19_782 * SECONDS_PER_DAY), "2024-02-29");
+        assert_eq!(date(19_783 * SECONDS_PER_DAY), "2024-03-01");
+        assert_eq!(date_time(1_709_993_100), "2024-03-09 14:05:00");
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        // Saturday 2024-03-09, Sunday 2024-03-10 and Monday 2024-03-11
+        let saturday = 19_791 * SECONDS_PER_DAY;
+        let entries = [
+            entry(saturday, None, Some(1.0)),
+            entry(saturday + SECONDS_PER_DAY, None, Some(2.0)),
+            entry(saturday + 2 * SECONDS_PER_DAY, None, None),
+            // before the first Monday after the epoch
+            entry(2 * SECONDS_PER_DAY, None, None),
+        ];
+
+        let rows = group(&entries, GroupBy::Week);
+        let keys: Vec<&str> = rows.keys().map(String::as_str).collect();
+        assert_eq!(keys, vec!["1970-01-01", "2024-03-04", "2024-03-11"]);
+        let week = &rows["2024-03-04"];
+        assert_eq!((week.runs, week.cost, week.unpriced), (2, 3.0, 0));
+        assert_eq!(rows["2024-03-11"].unpriced, 1);
+    }
+
+    #[test]
+    fn runs_are_grouped_by_day_and_repo() {
+        let entries = [
+            entry(0, Some("/a"), Some(1.0)),
+            entry(SECONDS_PER_DAY - 1, Some("/b"), Some(1.0)),
+            entry(SECONDS_PER_DAY, None, Some(1.0)),
+        ];
+
+        let days = group(&entries, GroupBy::Day);
+        assert_eq!(days["1970-01-01"].runs, 2);
+        assert_eq!(days["1970-01-02"].runs, 1);
+        assert_eq!(days["1970-01-01"].prompt_tokens, 200);
+
+        let repos = group(&entries, GroupBy::Repo);
+        let keys: Vec<&str> = repos.keys().map(String::as_str).collect();
+        assert_eq!(keys, vec!["(none)", "/a", "/b"]);
+    }
+}
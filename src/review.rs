@@ -0,0 +1,290 @@
+use anyhow::Context;
+use futures::{StreamExt, TryStreamExt};
+
+use crate::baseline::Baseline;
+use crate::cache::Cache;
+use crate::config::Config;
+use crate::context::{self, ContextBuilder};
+use crate::diff::FileDiff;
+use crate::output::RunMetadata;
+use crate::reviewer::Reviewer;
+use crate::{anchor, chunk, cost, directive, Changes, CommentType, Review};
+
+/// The requests a review will make
+pub struct ReviewPlan {
+    system_prompt: String,
+    files: Vec<FileDiff>,
+    directives: Vec<directive::Directive>,
+    /// The prompt for each chunk of the diff
+    prompts: Vec<String>,
+    /// Files that are larger than the token budget
+    pub skipped_files: Vec<String>,
+    /// Files left out to keep the estimated cost under `max_cost`
+    pub trimmed_files: Vec<String>,
+    cache: Option<Cache>,
+    /// How many of the chunks have a cached review
+    pub cached: usize,
+    /// The cost of the chunks that aren't cached
+    pub estimate: cost::Estimate,
+}
+
+/// Builds the system prompt and splits the changes into chunks, leaving out the least important
+/// files (or giving up) if the review is estimated to cost more than `max_cost`
+pub fn plan(
+    custom_prompt: Option<String>,
+    changes: &Changes,
+    config: &Config,
+) -> anyhow::Result<ReviewPlan> {
+    let default_prompt = r#"You are a helpful assistant that reviews code. The types of responses you can leave are "Nitpick", "LeftoverDebug", "UnnecessaryComment", "StyleIssue", "Question", "Issue", "Suggestion", "Idea". Also, quote the line of code that you are commenting on and give its path and line numbers, counting lines from the hunk headers (`@@ -old,count +new,count @@`) in the diff. Keep in mind that you may not see the entire file, only a diff that shows the sections that changed. This means that you may see variables and functions being used without seeing where they are defined. You are being invoked on code that compiles and passes all tests (you are simply a last pass sanity check).
+
+Nitpick: Small style issues, small issues in performance (e.g. cloning a vector when passing by reference would work).
+LeftoverDebug: Debug statements, println! statements, etc. that were probably left in by mistake.
+UnnecessaryComment: Comments that are not needed, or explain something overly-obvious. Be very strict about this. Comments that explain what the code does are not needed. The only comments that are needed are ones provided as documentation for public parts of the API, and those that explain *why* the code is the way it is (rather than what it does).
+StyleIssue: Style issues that do not fall under the other categories.
+Question: Questions about the code, or questions that the user should answer before merging (e.g. have you updated the docs?).
+Issue: Issues with the code that are not style related.
+Suggestion: Suggestions for improvements.
+Idea: Ideas for improvements.
+
+If a comment on added or unchanged lines has an obvious mechanical fix (such as removing a debug statement or an unnecessary comment), include it as `fix`, copying the lines being replaced exactly. Otherwise leave `fix` null.
+
+Remember, the code you are reviewing has already been compiled without errors and passed all tests. There is no possibility that the code would not compile, and there are no errors in the code that would prevent it from compiling."#;
+
+    let mut system_prompt = custom_prompt.unwrap_or_else(|| default_prompt.to_string());
+    let comment_types = config.comment_types();
+    if comment_types.len() < CommentType::ALL.len() {
+        let allowed: Vec<String> = comment_types.iter().map(|t| format!("\"{}\"", t)).collect();
+        system_prompt.push_str(&format!(
+            "\n\nOnly leave comments of these types: {}.",
+            allowed.join(", ")
+        ));
+    }
+    if let Some(extra_prompt) = &config.extra_prompt {
+        system_prompt.push_str("\n\n");
+        system_prompt.push_str(extra_prompt);
+    }
+
+    if let Some(commit) = &changes.commit {
+        system_prompt.push_str(&format!(
+            "\n\nThe diff is a single commit. Its message, which explains the intent of the changes, is:\n\n{}",
+            commit.message
+        ));
+    }
+
+    let context = ContextBuilder::new(changes, config);
+    if context.is_enabled() {
+        system_prompt.push_str("\n\n");
+        system_prompt.push_str(context::PROMPT);
+    }
+
+    let files = crate::diff::parse(&changes.diff);
+    let context = context.gather(&files);
+    let directives = directive::find(&files);
+    if let Some(directives_prompt) = directive::prompt(&directives) {
+        system_prompt.push_str("\n\n");
+        system_prompt.push_str(&directives_prompt);
+    }
+
+    let model = config.model();
+    let price = config.price();
+    let cache = config.cache().then(Cache::open).flatten();
+    let is_cached = |prompt: &String| {
+        cache
+            .as_ref()
+            .is_some_and(|cache| cache.contains(&Cache::key(&system_prompt, config, prompt)))
+    };
+
+    // the system prompt is sent with every chunk, so it counts against each one's budget
+    let budget = config
+        .chunk_tokens()
+        .saturating_sub(chunk::estimate_tokens(&system_prompt));
+    let mut reviewed = files.clone();
+    let mut trimmed_files = Vec::new();
+    loop {
+        let (chunks, skipped_files) = chunk::split(&reviewed, budget);
+        let prompts: Vec<String> = chunks
+            .iter()
+            .map(|chunk| context.prompt(&chunk.diff))
+            .collect();
+        let estimate = cost::Estimate::new(
+            price,
+            &system_prompt,
+            prompts
+                .iter()
+                .filter(|prompt| !is_cached(prompt))
+                .map(String::as_str),
+        );
+
+        let over_budget = match config.max_cost {
+            Some(max_cost) => {
+                let cost = estimate.cost.with_context(|| {
+                    format!(
+                        "The price of {} is unknown, so the review can't be checked against the maximum cost; set `price` to its dollars per million prompt and completion tokens",
+                        model
+                    )
+                })?;
+                (cost > max_cost).then_some((cost, max_cost))
+            }
+            None => None,
+        };
+        let Some((cost, max_cost)) = over_budget else {
+            if prompts.is_empty() {
+                anyhow::bail!("Every changed file is larger than the token budget");
+            }
+            let cached = prompts.iter().filter(|prompt| is_cached(prompt)).count();
+            return Ok(ReviewPlan {
+                system_prompt,
+                files,
+                directives,
+                prompts,
+                skipped_files,
+                trimmed_files,
+                cache,
+                cached,
+                estimate,
+            });
+        };
+
+        match config.over_budget() {
+            cost::OverBudget::Abort => anyhow::bail!(
+                "The review would cost about ${:.2}, more than the maximum of ${:.2} (pass `--over-budget trim` to leave out the least important files)",
+                cost,
+                max_cost
+            ),
+            cost::OverBudget::Trim if reviewed.len() <= 1 => anyhow::bail!(
+                "Even the most important file would cost about ${:.2} to review, more than the maximum of ${:.2}",
+                cost,
+                max_cost
+            ),
+            cost::OverBudget::Trim => {
+                // the least important file, and the largest of those
+                let index = (0..reviewed.len())
+                    .min_by_key(|&i| {
+                        let file = &reviewed[i];
+                        (
+                            cost::priority(file.path()),
+                            std::cmp::Reverse(file.raw().len()),
+                        )
+                    })
+                    .unwrap_or_default();
+                trimmed_files.push(reviewed.remove(index).path().to_string());
+            }
+        }
+    }
+}
+
+impl dyn Reviewer + '_ {
+    /// Reviews the changes chunk by chunk, reusing cached reviews, and drops the comments that
+    /// are filtered out, ignored by directives or in `baseline`. The metadata's cost covers
+    /// every request the reviewer has made, not just this review's.
+    pub async fn review_changes(
+        &self,
+        custom_prompt: Option<String>,
+        changes: &Changes,
+        config: &Config,
+        baseline: Option<&Baseline>,
+        verbose: bool,
+    ) -> anyhow::Result<(Review, RunMetadata)> {
+        let ReviewPlan {
+            system_prompt,
+            files,
+            directives,
+            prompts,
+            skipped_files,
+            trimmed_files,
+            cache,
+            cached: _,
+            estimate,
+        } = plan(custom_prompt, changes, config)?;
+
+        if verbose {
+            eprintln!(
+                "Sending changes to AI for review in {} chunk(s)...",
+                prompts.len()
+            );
+            if let Some(cost) = estimate.cost {
+                eprintln!("Estimated cost: ${:.2}", cost);
+            }
+        }
+
+        let reviews: Vec<(Review, bool, Option<String>)> = futures::stream::iter(&prompts)
+            .map(|prompt| {
+                let cache = &cache;
+                let system_prompt = &system_prompt;
+                async move {
+                    let key = Cache::key(system_prompt, config, prompt);
+                    if let Some(review) = cache.as_ref().and_then(|cache| cache.get(&key)) {
+                        return anyhow::Ok((review, true, None));
+                    }
+                    let review = self.review(system_prompt, prompt).await?;
+                    let warning = cache
+                        .as_ref()
+                        .and_then(|cache| cache.put(&key, &review).err())
+                        .map(|e| format!("Failed to cache the review: {:#}", e));
+                    anyhow::Ok((review, false, warning))
+                }
+            })
+            .buffered(config.concurrency())
+            .try_collect()
+            .await?;
+        let cached = reviews.iter().filter(|(_, cached, _)| *cached).count();
+        if verbose && cached > 0 {
+            eprintln!("Reused the cached review of {} chunk(s)", cached);
+        }
+        let mut warnings = Vec::new();
+        let mut review = Review {
+            comments: Vec::new(),
+        };
+        for (chunk_review, _, warning) in reviews {
+            review.comments.extend(chunk_review.comments);
+            warnings.extend(warning);
+        }
+
+        if verbose {
+            let usage = self.usage();
+            eprintln!(
+                "Used {} prompt tokens and {} completion tokens",
+                usage.prompt_tokens, usage.completion_tokens
+            );
+        }
+        let comment_types = config.comment_types();
+        review
+            .comments
+            .retain(|comment| comment_types.contains(&comment.comment_type));
+
+        anchor::anchor_comments(&mut review.comments, &files);
+        if verbose {
+            let unanchored = review.comments.iter().filter(|c| !c.anchored).count();
+            if unanchored > 0 {
+                eprintln!("{} comment(s) could not be located in the diff", unanchored);
+            }
+        }
+
+        let ignored = directive::apply(&mut review.comments, &directives);
+        if verbose && ignored > 0 {
+            eprintln!("{} comment(s) dropped by b4sam:ignore directives", ignored);
+        }
+
+        let before = review.comments.len();
+        if let Some(baseline) = baseline {
+            review
+                .comments
+                .retain(|comment| !baseline.contains(comment));
+        }
+        let suppressed = before - review.comments.len();
+
+        let metadata = RunMetadata {
+            model: self.model().to_string(),
+            base: changes.base.clone(),
+            cost: self.cost(),
+            skipped_files,
+            trimmed_files,
+            suppressed,
+            carried_over: 0,
+            commit: changes.commit.clone(),
+            warnings,
+        };
+
+        Ok((review, metadata))
+    }
+}
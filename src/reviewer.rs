@@ -0,0 +1,427 @@
+use std::sync::Mutex;
+
+use anyhow::Context;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use tysm::chat_completions::{
+    ChatClient, ChatMessage, ChatUsage, JsonSchemaFormat, ResponseFormat,
+};
+
+use crate::config::Config;
+use crate::replay;
+use crate::Review;
+
+const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1/";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+/// Most output tokens to ask for, by model name prefix, since Anthropic rejects requests for more
+/// than a model can write. The longest matching prefix wins.
+const ANTHROPIC_MAX_TOKENS: [(&str, u32); 4] = [
+    ("claude-", 16384),
+    ("claude-3-", 4096),
+    ("claude-3-5-", 8192),
+    ("claude-3-7-", 16384),
+];
+/// For models whose names aren't in the table
+const ANTHROPIC_DEFAULT_MAX_TOKENS: u32 = 8192;
+const AZURE_API_VERSION: &str = "2024-10-21";
+
+/// The LLM vendor that reviews the changes
+#[derive(clap::ValueEnum, serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// OpenAI, or any OpenAI-compatible server (Ollama, llama.cpp, ...) when `base_url` is set
+    #[default]
+    #[value(name = "openai")]
+    OpenAi,
+    /// Anthropic's Messages API
+    Anthropic,
+    /// An Azure OpenAI deployment (the model is the deployment name)
+    Azure,
+}
+
+/// A model that can answer a prompt with JSON matching a schema.
+///
+/// Use [`dyn Reviewer::chat`] and [`dyn Reviewer::review`] for typed responses, and
+/// [`dyn Reviewer::review_changes`] to review a whole set of changes.
+#[async_trait::async_trait]
+pub trait Reviewer: Send + Sync {
+    /// Sends the prompts and returns the model's response, which should conform to `schema`
+    async fn chat_json(
+        &self,
+        system_prompt: &str,
+        prompt: &str,
+        schema: &JsonSchemaFormat,
+    ) -> anyhow::Result<serde_json::Value>;
+
+    fn model(&self) -> &str;
+
+    /// Tokens used by every request made so far
+    fn usage(&self) -> ChatUsage;
+
+    /// The cost in dollars of every request made so far, if the model's prices are known
+    fn cost(&self) -> Option<f64>;
+}
+
+impl dyn Reviewer + '_ {
+    pub async fn chat<T: DeserializeOwned + JsonSchema>(
+        &self,
+        system_prompt: &str,
+        prompt: &str,
+    ) -> anyhow::Result<T> {
+        let schema = JsonSchemaFormat::new::<T>();
+        let response = self.chat_json(system_prompt, prompt, &schema).await?;
+        serde_json::from_value(response.clone())
+            .with_context(|| format!("Model response did not match the schema: {}", response))
+    }
+
+    pub async fn review(&self, system_prompt: &str, diff: &str) -> anyhow::Result<Review> {
+        self.chat(system_prompt, diff).await
+    }
+}
+
+/// Creates the reviewer for the configured provider, reading its API key from the environment.
+///
+/// With `B4SAM_REPLAY` set, responses come from recorded fixtures instead, and with
+/// `B4SAM_RECORD` set, they are recorded.
+pub fn reviewer(config: &Config) -> anyhow::Result<Box<dyn Reviewer>> {
+    if let Some(dir) = std::env::var_os(replay::REPLAY_ENV) {
+        return Ok(Box::new(replay::Replayer {
+            dir: dir.into(),
+            model: config.model().to_string(),
+        }));
+    }
+
+    let reviewer = provider_reviewer(config)?;
+    Ok(match std::env::var_os(replay::RECORD_ENV) {
+        Some(dir) => Box::new(replay::Recorder {
+            inner: reviewer,
+            dir: dir.into(),
+        }),
+        None => reviewer,
+    })
+}
+
+fn provider_reviewer(config: &Config) -> anyhow::Result<Box<dyn Reviewer>> {
+    let model = config.model();
+    let base_url = config.base_url.as_deref();
+
+    Ok(match config.provider.unwrap_or_default() {
+        Provider::OpenAi => {
+            let client = match ChatClient::from_env(model) {
+                Ok(client) => client,
+                // Local OpenAI-compatible servers usually don't check the key
+                Err(_) if base_url.is_some() => ChatClient::new("", model),
+                Err(e) => return Err(e.into()),
+            };
+            let client = match base_url {
+                Some(url) => {
+                    reqwest::Url::parse(url)
+                        .with_context(|| format!("Invalid base URL: {}", url))?;
+                    client.with_url(url)
+                }
+                None => client,
+            };
+            Box::new(OpenAiReviewer {
+                client,
+                price: config.price(),
+            })
+        }
+        Provider::Anthropic => Box::new(AnthropicReviewer {
+            api_key: api_key("ANTHROPIC_API_KEY")?,
+            base_url: base_url.unwrap_or(ANTHROPIC_BASE_URL).to_string(),
+            model: model.to_string(),
+            price: config.price(),
+            usage: Mutex::new(ChatUsage::default()),
+        }),
+        Provider::Azure => {
+            let endpoint = match base_url {
+                Some(url) => url.to_string(),
+                None => api_key("AZURE_OPENAI_ENDPOINT")?,
+            };
+            Box::new(AzureReviewer {
+                api_key: api_key("AZURE_OPENAI_API_KEY")?,
+                endpoint,
+                deployment: model.to_string(),
+                api_version: config
+                    .api_version
+                    .as_deref()
+                    .unwrap_or(AZURE_API_VERSION)
+                    .to_string(),
+                price: config.price(),
+                usage: Mutex::new(ChatUsage::default()),
+            })
+        }
+    })
+}
+
+fn api_key(var: &str) -> anyhow::Result<String> {
+    std::env::var(var).with_context(|| format!("The {} environment variable is not set", var))
+}
+
+/// OpenAI's chat completions API (or a compatible server), through `tysm`
+pub struct OpenAiReviewer {
+    client: ChatClient,
+    /// Dollars per million prompt and completion tokens, if known
+    price: Option<(f64, f64)>,
+}
+
+#[async_trait::async_trait]
+impl Reviewer for OpenAiReviewer {
+    async fn chat_json(
+        &self,
+        system_prompt: &str,
+        prompt: &str,
+        schema: &JsonSchemaFormat,
+    ) -> anyhow::Result<serde_json::Value> {
+        let response = self
+            .client
+            .chat_with_messages_raw(
+                vec![
+                    ChatMessage::system(system_prompt),
+                    ChatMessage::user(prompt),
+                ],
+                ResponseFormat::JsonSchema {
+                    json_schema: schema.clone(),
+                },
+            )
+            .await?;
+
+        serde_json::from_str(&response)
+            .with_context(|| format!("Model response was not valid JSON: {}", response))
+    }
+
+    fn model(&self) -> &str {
+        &self.client.model
+    }
+
+    fn usage(&self) -> ChatUsage {
+        self.client.usage()
+    }
+
+    fn cost(&self) -> Option<f64> {
+        crate::cost::usage_cost(self.price, self.usage())
+    }
+}
+
+/// Anthropic's Messages API. Structured output is obtained by forcing the model to call a tool
+/// whose input schema is the response schema.
+pub struct AnthropicReviewer {
+    api_key: String,
+    base_url: String,
+    model: String,
+    /// Dollars per million prompt and completion tokens, if known
+    price: Option<(f64, f64)>,
+    usage: Mutex<ChatUsage>,
+}
+
+#[derive(serde::Deserialize)]
+struct AnthropicResponse {
+    content: Vec<AnthropicContent>,
+    usage: AnthropicUsage,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicContent {
+    ToolUse {
+        input: serde_json::Value,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(serde::Deserialize)]
+struct AnthropicUsage {
+    input_tokens: u32,
+    output_tokens: u32,
+}
+
+#[async_trait::async_trait]
+impl Reviewer for AnthropicReviewer {
+    async fn chat_json(
+        &self,
+        system_prompt: &str,
+        prompt: &str,
+        schema: &JsonSchemaFormat,
+    ) -> anyhow::Result<serde_json::Value> {
+        let url = format!("{}/messages", self.base_url.trim_end_matches('/'));
+        let body = serde_json::json!({
+            "model": self.model,
+            "max_tokens": anthropic_max_tokens(&self.model),
+            "system": system_prompt,
+            "messages": [{ "role": "user", "content": prompt }],
+            "tools": [{
+                "name": schema.name,
+                "description": "Submit your response",
+                "input_schema": schema.schema,
+            }],
+            "tool_choice": { "type": "tool", "name": schema.name },
+        });
+
+        let response = reqwest::Client::new()
+            .post(&url)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(&body)
+            .send()
+            .await
+            .with_context(|| format!("Failed to send request to {}", url))?;
+
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            anyhow::bail!("Anthropic API returned {}: {}", status, text);
+        }
+
+        let response: AnthropicResponse = serde_json::from_str(&text)
+            .with_context(|| format!("Unexpected response from Anthropic API: {}", text))?;
+
+        *self.usage.lock().unwrap() += ChatUsage {
+            prompt_tokens: response.usage.input_tokens,
+            completion_tokens: response.usage.output_tokens,
+            total_tokens: response.usage.input_tokens + response.usage.output_tokens,
+            ..ChatUsage::default()
+        };
+
+        response
+            .content
+            .into_iter()
+            .find_map(|content| match content {
+                AnthropicContent::ToolUse { input } => Some(input),
+                AnthropicContent::Other => None,
+            })
+            .with_context(|| format!("Anthropic API response had no tool call: {}", text))
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn usage(&self) -> ChatUsage {
+        *self.usage.lock().unwrap()
+    }
+
+    fn cost(&self) -> Option<f64> {
+        crate::cost::usage_cost(self.price, self.usage())
+    }
+}
+
+/// The most output tokens `model` can write in a response
+fn anthropic_max_tokens(model: &str) -> u32 {
+    ANTHROPIC_MAX_TOKENS
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map_or(ANTHROPIC_DEFAULT_MAX_TOKENS, |&(_, max_tokens)| max_tokens)
+}
+
+/// An Azure OpenAI deployment, which speaks the chat completions API with a different URL scheme
+/// and authentication header
+pub struct AzureReviewer {
+    api_key: String,
+    endpoint: String,
+    deployment: String,
+    api_version: String,
+    /// Dollars per million prompt and completion tokens, if known
+    price: Option<(f64, f64)>,
+    usage: Mutex<ChatUsage>,
+}
+
+#[derive(serde::Deserialize)]
+struct ChatCompletion {
+    choices: Vec<ChatCompletionChoice>,
+    usage: ChatUsage,
+}
+
+#[derive(serde::Deserialize)]
+struct ChatCompletionChoice {
+    message: ChatCompletionMessage,
+}
+
+#[derive(serde::Deserialize)]
+struct ChatCompletionMessage {
+    content: Option<String>,
+    refusal: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl Reviewer for AzureReviewer {
+    async fn chat_json(
+        &self,
+        system_prompt: &str,
+        prompt: &str,
+        schema: &JsonSchemaFormat,
+    ) -> anyhow::Result<serde_json::Value> {
+        let url = format!(
+            "{}/openai/deployments/{}/chat/completions?api-version={}",
+            self.endpoint.trim_end_matches('/'),
+            self.deployment,
+            self.api_version
+        );
+        let body = serde_json::json!({
+            "messages": [ChatMessage::system(system_prompt), ChatMessage::user(prompt)],
+            "response_format": ResponseFormat::JsonSchema { json_schema: schema.clone() },
+        });
+
+        let response = reqwest::Client::new()
+            .post(&url)
+            .header("api-key", &self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .with_context(|| format!("Failed to send request to {}", url))?;
+
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            anyhow::bail!("Azure OpenAI API returned {}: {}", status, text);
+        }
+
+        let completion: ChatCompletion = serde_json::from_str(&text)
+            .with_context(|| format!("Unexpected response from Azure OpenAI API: {}", text))?;
+        *self.usage.lock().unwrap() += completion.usage;
+
+        let message = completion
+            .choices
+            .into_iter()
+            .next()
+            .context("Azure OpenAI API returned no choices")?
+            .message;
+        if let Some(refusal) = message.refusal.filter(|r| !r.trim().is_empty()) {
+            anyhow::bail!("The model refused to respond: {}", refusal);
+        }
+        let content = message
+            .content
+            .context("Azure OpenAI API returned an empty message")?;
+
+        serde_json::from_str(&content)
+            .with_context(|| format!("Model response was not valid JSON: {}", content))
+    }
+
+    fn model(&self) -> &str {
+        &self.deployment
+    }
+
+    fn usage(&self) -> ChatUsage {
+        *self.usage.lock().unwrap()
+    }
+
+    fn cost(&self) -> Option<f64> {
+        crate::cost::usage_cost(self.price, self.usage())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anthropic_requests_ask_for_no_more_tokens_than_the_model_can_write() {
+        assert_eq!(anthropic_max_tokens("claude-3-haiku-20240307"), 4096);
+        assert_eq!(anthropic_max_tokens("claude-3-5-sonnet-20241022"), 8192);
+        assert_eq!(anthropic_max_tokens("claude-3-5-haiku-latest"), 8192);
+        assert_eq!(anthropic_max_tokens("claude-3-7-sonnet-latest"), 16384);
+        assert_eq!(anthropic_max_tokens("claude-sonnet-4-20250514"), 16384);
+        assert_eq!(anthropic_max_tokens("some-proxy-model"), 8192);
+    }
+}
@@ -0,0 +1,110 @@
+use serde_json::{json, Value};
+
+use crate::output::RunMetadata;
+use crate::{Comment, CommentType, Review, Severity, Side};
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Builds a SARIF 2.1.0 log with one rule per [`CommentType`] and one result per comment. The
+/// reviews (of each commit, with `--per-commit`) are merged into a single run, since GitHub code
+/// scanning rejects logs with several runs from the same tool.
+pub fn sarif_log(reviews: &[(&Review, &RunMetadata)]) -> Value {
+    let rules: Vec<Value> = CommentType::ALL
+        .iter()
+        .map(|comment_type| {
+            json!({
+                "id": comment_type.to_string(),
+                "name": comment_type.to_string(),
+                "shortDescription": { "text": rule_description(*comment_type) },
+                "defaultConfiguration": { "level": level(*comment_type) },
+            })
+        })
+        .collect();
+
+    let results: Vec<Value> = reviews
+        .iter()
+        .flat_map(|(review, _)| review.comments.iter().map(result))
+        .collect();
+    let metadata = || reviews.iter().map(|(_, metadata)| *metadata);
+    // the base of the oldest commit, which is the base of them all
+    let base = metadata().next().and_then(|metadata| metadata.base.clone());
+    let cost: Option<f64> = metadata().map(|metadata| metadata.cost).sum();
+    let commits: Vec<_> = metadata().filter_map(|m| m.commit.as_ref()).collect();
+
+    let mut properties = json!({
+        "model": metadata().next().map(|metadata| metadata.model.as_str()),
+        "base": base,
+        "cost": cost,
+        "suppressed": metadata().map(|m| m.suppressed).sum::<usize>(),
+        "carriedOver": metadata().map(|m| m.carried_over).sum::<usize>(),
+    });
+    if !commits.is_empty() {
+        properties["commits"] = json!(commits);
+    }
+
+    json!({
+        "$schema": SARIF_SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "b4sam",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                    "rules": rules,
+                }
+            },
+            "properties": properties,
+            "results": results,
+        }],
+    })
+}
+
+fn result(comment: &Comment) -> Value {
+    let rule_index = CommentType::ALL
+        .iter()
+        .position(|t| *t == comment.comment_type)
+        .expect("every comment type has a rule");
+
+    let mut physical_location = json!({
+        "artifactLocation": { "uri": comment.path, "uriBaseId": "%SRCROOT%" },
+    });
+    // SARIF lines start at 1, and code scanning only shows locations in the new version of a file
+    if comment.start_line > 0 && comment.side == Side::New {
+        physical_location["region"] = json!({
+            "startLine": comment.start_line,
+            "endLine": comment.end_line.max(comment.start_line),
+            "snippet": { "text": comment.line.trim() },
+        });
+    }
+
+    json!({
+        "ruleId": comment.comment_type.to_string(),
+        "ruleIndex": rule_index,
+        "level": level(comment.comment_type),
+        "message": { "text": comment.comment },
+        "locations": [{ "physicalLocation": physical_location }],
+        "properties": { "anchored": comment.anchored },
+    })
+}
+
+fn level(comment_type: CommentType) -> &'static str {
+    match comment_type.severity() {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Info => "note",
+    }
+}
+
+fn rule_description(comment_type: CommentType) -> &'static str {
+    match comment_type {
+        CommentType::Nitpick => "Small style or performance issue",
+        CommentType::LeftoverDebug => "Debug statement that was probably left in by mistake",
+        CommentType::UnnecessaryComment => "Comment that is not needed",
+        CommentType::StyleIssue => "Style issue",
+        CommentType::Question => "Question to answer before merging",
+        CommentType::Issue => "Issue with the code that is not style related",
+        CommentType::Suggestion => "Suggestion for an improvement",
+        CommentType::Idea => "Idea for an improvement",
+    }
+}
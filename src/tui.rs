@@ -0,0 +1,364 @@
+use std::io::IsTerminal;
+use std::process::Command;
+
+use anyhow::Context;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::Frame;
+
+use b4sam::diff::{DiffLine, FileDiff, Hunk, LineKind};
+use b4sam::{Comment, CommentType, Review, Side};
+
+/// The UI is drawn on stderr, so stdout can still be redirected to export the review
+type Terminal = ratatui::Terminal<CrosstermBackend<std::io::Stderr>>;
+
+/// What the user decided about a comment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Decision {
+    #[default]
+    Undecided,
+    Accepted,
+    Dismissed,
+    FalsePositive,
+}
+
+impl Decision {
+    /// Whether the comment should still be reported
+    pub fn is_remaining(self) -> bool {
+        matches!(self, Decision::Undecided | Decision::Accepted)
+    }
+
+    fn label(self) -> Span<'static> {
+        match self {
+            Decision::Undecided => Span::raw("[ ]"),
+            Decision::Accepted => Span::styled("[✓]", Color::Green),
+            Decision::Dismissed => Span::styled("[✗]", Color::DarkGray),
+            Decision::FalsePositive => Span::styled("[FP]", Color::Magenta),
+        }
+    }
+}
+
+/// A line of the comment list: group headers, or the comment at an index of the review
+enum Row {
+    File(String),
+    Type(CommentType),
+    Comment(usize),
+}
+
+struct App<'a> {
+    review: &'a Review,
+    files: &'a [FileDiff],
+    decisions: Vec<Decision>,
+    rows: Vec<Row>,
+    /// Index into `rows`, always of a `Row::Comment`
+    selected: usize,
+    status: String,
+}
+
+/// Lets the user go through the comments one by one, deciding what to do with each. Returns a
+/// decision for every comment of the review, in order.
+pub fn run(review: &Review, files: &[FileDiff]) -> anyhow::Result<Vec<Decision>> {
+    if !std::io::stderr().is_terminal() {
+        anyhow::bail!("--interactive needs a terminal");
+    }
+    if review.comments.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut app = App::new(review, files);
+    let mut terminal = init()?;
+    let result = app.event_loop(&mut terminal);
+    restore();
+    result?;
+
+    Ok(app.decisions)
+}
+
+fn init() -> anyhow::Result<Terminal> {
+    terminal::enable_raw_mode()?;
+    execute!(std::io::stderr(), EnterAlternateScreen)?;
+    Ok(Terminal::new(CrosstermBackend::new(std::io::stderr()))?)
+}
+
+fn restore() {
+    // best effort, like `ratatui::restore`, so the terminal is restored as much as possible
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(std::io::stderr(), LeaveAlternateScreen);
+}
+
+impl<'a> App<'a> {
+    fn new(review: &'a Review, files: &'a [FileDiff]) -> Self {
+        // group by file, then by type in the order of `CommentType::ALL`
+        let mut order: Vec<usize> = (0..review.comments.len()).collect();
+        order.sort_by_key(|&i| {
+            let comment = &review.comments[i];
+            let type_index = CommentType::ALL
+                .iter()
+                .position(|t| *t == comment.comment_type);
+            (comment.path.clone(), type_index, comment.start_line)
+        });
+
+        let mut rows = Vec::new();
+        let mut last: Option<(&str, CommentType)> = None;
+        for i in order {
+            let comment = &review.comments[i];
+            if last.is_none_or(|(path, _)| path != comment.path) {
+                rows.push(Row::File(comment.path.clone()));
+                rows.push(Row::Type(comment.comment_type));
+            } else if last.is_some_and(|(_, t)| t != comment.comment_type) {
+                rows.push(Row::Type(comment.comment_type));
+            }
+            last = Some((&comment.path, comment.comment_type));
+            rows.push(Row::Comment(i));
+        }
+
+        let mut app = App {
+            review,
+            files,
+            decisions: vec![Decision::default(); review.comments.len()],
+            rows,
+            selected: 0,
+            status: String::new(),
+        };
+        app.move_selection(1);
+        app
+    }
+
+    fn event_loop(&mut self, terminal: &mut Terminal) -> anyhow::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            self.status.clear();
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+                KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+                KeyCode::Char('a') => self.decide(Decision::Accepted),
+                KeyCode::Char('d') => self.decide(Decision::Dismissed),
+                KeyCode::Char('f') => self.decide(Decision::FalsePositive),
+                KeyCode::Char('u') => self.decide(Decision::Undecided),
+                KeyCode::Char('e') | KeyCode::Enter => {
+                    restore();
+                    let result = open_in_editor(self.comment());
+                    *terminal = init()?;
+                    if let Err(e) = result {
+                        self.status = format!("{:#}", e);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn comment_index(&self) -> usize {
+        match self.rows[self.selected] {
+            Row::Comment(i) => i,
+            _ => unreachable!("only comments can be selected"),
+        }
+    }
+
+    fn comment(&self) -> &'a Comment {
+        &self.review.comments[self.comment_index()]
+    }
+
+    /// Moves to the next comment in `direction`, skipping headers
+    fn move_selection(&mut self, direction: isize) {
+        let mut row = self.selected;
+        loop {
+            row = match row.checked_add_signed(direction) {
+                Some(row) if row < self.rows.len() => row,
+                _ => return,
+            };
+            if matches!(self.rows[row], Row::Comment(_)) {
+                self.selected = row;
+                return;
+            }
+        }
+    }
+
+    fn decide(&mut self, decision: Decision) {
+        let i = self.comment_index();
+        self.decisions[i] = decision;
+        if decision != Decision::Undecided {
+            self.move_selection(1);
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, help_area] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(frame.area());
+        let [list_area, detail_area] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Fill(1)]).areas(main);
+        let [comment_area, hunk_area] =
+            Layout::vertical([Constraint::Percentage(35), Constraint::Fill(1)]).areas(detail_area);
+
+        let items: Vec<ListItem> = self
+            .rows
+            .iter()
+            .map(|row| match row {
+                Row::File(path) => ListItem::new(Line::from(path.as_str().bold())),
+                Row::Type(comment_type) => {
+                    ListItem::new(Line::from(format!("  {}", comment_type).italic()))
+                }
+                Row::Comment(i) => {
+                    let comment = &self.review.comments[*i];
+                    ListItem::new(Line::from(vec![
+                        Span::raw("    "),
+                        self.decisions[*i].label(),
+                        Span::raw(format!(" {} ", comment.location())),
+                        Span::styled(
+                            comment
+                                .comment
+                                .lines()
+                                .next()
+                                .unwrap_or_default()
+                                .to_string(),
+                            Color::Gray,
+                        ),
+                    ]))
+                }
+            })
+            .collect();
+        let remaining = self.decisions.iter().filter(|d| d.is_remaining()).count();
+        let list = List::new(items)
+            .block(Block::bordered().title(format!(
+                " Comments ({} of {} remaining) ",
+                remaining,
+                self.decisions.len()
+            )))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected(Some(self.selected));
+        frame.render_stateful_widget(list, list_area, &mut state);
+
+        let comment = self.comment();
+        let mut text = Text::from(vec![
+            Line::from(vec![
+                Span::styled(comment.comment_type.to_string(), Color::Yellow).bold(),
+                Span::raw(format!(" in {}", comment.location())),
+            ]),
+            Line::from(format!("line: {}", comment.line.trim())).dark_gray(),
+            Line::default(),
+        ]);
+        text.extend(Text::from(comment.comment.as_str()));
+        frame.render_widget(
+            Paragraph::new(text)
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title(" Comment ")),
+            comment_area,
+        );
+
+        let hunk = match hunk_for(comment, self.files) {
+            Some(hunk) => hunk_text(hunk, comment),
+            None => Text::from("(not found in the diff)".dark_gray()),
+        };
+        frame.render_widget(
+            Paragraph::new(hunk).block(Block::bordered().title(" Diff ")),
+            hunk_area,
+        );
+
+        let help = if self.status.is_empty() {
+            "j/k: move  a: accept  d: dismiss  f: false positive  u: undo  e: open in $EDITOR  q: quit and print the remaining comments".to_string()
+        } else {
+            self.status.clone()
+        };
+        frame.render_widget(Line::from(help).dark_gray(), help_area);
+    }
+}
+
+fn line_number(line: &DiffLine, side: Side) -> Option<u32> {
+    match side {
+        Side::Old => line.old_line,
+        Side::New => line.new_line,
+    }
+}
+
+/// The hunk containing the first line of an anchored comment
+fn hunk_for<'a>(comment: &Comment, files: &'a [FileDiff]) -> Option<&'a Hunk> {
+    if !comment.anchored {
+        return None;
+    }
+
+    let file = files.iter().find(|file| {
+        let path = match comment.side {
+            Side::Old => &file.old_path,
+            Side::New => &file.new_path,
+        };
+        path.as_deref() == Some(comment.path.as_str())
+    })?;
+    file.hunks.iter().find(|hunk| {
+        hunk.lines
+            .iter()
+            .any(|line| line_number(line, comment.side) == Some(comment.start_line))
+    })
+}
+
+/// The hunk with its changes colored and the commented lines highlighted, scrolled so the comment
+/// is near the top
+fn hunk_text(hunk: &Hunk, comment: &Comment) -> Text<'static> {
+    let commented = |line: &DiffLine| {
+        line_number(line, comment.side)
+            .is_some_and(|n| (comment.start_line..=comment.end_line).contains(&n))
+    };
+    let first = hunk.lines.iter().position(commented).unwrap_or(0);
+
+    let lines = hunk.lines.iter().skip(first.saturating_sub(5)).map(|line| {
+        let (prefix, color) = match line.kind {
+            LineKind::Context => (' ', Color::Reset),
+            LineKind::Added => ('+', Color::Green),
+            LineKind::Removed => ('-', Color::Red),
+        };
+        let number = line_number(line, comment.side)
+            .map(|n| format!("{:>5} ", n))
+            .unwrap_or_else(|| " ".repeat(6));
+        let mut style = Style::new().fg(color);
+        if commented(line) {
+            style = style.add_modifier(Modifier::REVERSED);
+        }
+        Line::from(vec![
+            Span::styled(number, Color::DarkGray),
+            Span::styled(format!("{}{}", prefix, line.text), style),
+        ])
+    });
+    Text::from_iter(lines)
+}
+
+/// Opens the commented file at the comment's line in `$VISUAL` or `$EDITOR`
+fn open_in_editor(comment: &Comment) -> anyhow::Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let root = b4sam::repo_root().context("Not in a git repository")?;
+
+    let mut command = Command::new("sh");
+    // run through the shell so that editors with arguments, like `code -w`, work
+    command
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg("sh")
+        .current_dir(root);
+    // removed lines are no longer in the file, so just open it
+    if comment.side == Side::New && comment.start_line > 0 {
+        command.arg(format!("+{}", comment.start_line));
+    }
+    let status = command
+        .arg(&comment.path)
+        .status()
+        .with_context(|| format!("Failed to run {}", editor))?;
+
+    if !status.success() {
+        anyhow::bail!("{} exited with {}", editor, status);
+    }
+    Ok(())
+}
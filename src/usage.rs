@@ -0,0 +1,89 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+
+use crate::reviewer::Reviewer;
+
+/// One run's use of the model, as a line of the ledger
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct Entry {
+    /// When the run finished, in seconds since the Unix epoch
+    pub time: u64,
+    pub model: String,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    /// The cost in dollars, if the model's prices are known
+    pub cost: Option<f64>,
+    /// The root of the repository the run was in
+    pub repo: Option<String>,
+    pub branch: Option<String>,
+    pub duration_secs: f64,
+}
+
+/// `$XDG_DATA_HOME/b4sam/usage.jsonl` (or the platform equivalent)
+fn ledger_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("b4sam").join("usage.jsonl"))
+}
+
+/// Appends the tokens the reviewer has used since `started` to the ledger. Runs that didn't call
+/// the model (e.g. because every chunk was cached) aren't recorded.
+pub fn record(reviewer: &dyn Reviewer, started: Instant) -> anyhow::Result<()> {
+    let usage = reviewer.usage();
+    if usage.prompt_tokens == 0 && usage.completion_tokens == 0 {
+        return Ok(());
+    }
+
+    let entry = Entry {
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs()),
+        model: reviewer.model().to_string(),
+        prompt_tokens: usage.prompt_tokens,
+        completion_tokens: usage.completion_tokens,
+        cost: reviewer.cost(),
+        repo: crate::repo_root().map(|root| root.display().to_string()),
+        branch: crate::history::current_branch(),
+        duration_secs: started.elapsed().as_secs_f64(),
+    };
+    append(&entry)
+}
+
+fn append(entry: &Entry) -> anyhow::Result<()> {
+    let path = ledger_path().context("No data directory on this platform")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    // one write per line, so runs finishing at the same time don't interleave
+    let line = format!("{}\n", serde_json::to_string(entry)?);
+    file.write_all(line.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Every run in the ledger, oldest first
+pub fn load() -> anyhow::Result<Vec<Entry>> {
+    let path = ledger_path().context("No data directory on this platform")?;
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Failed to parse line {} of {}", i + 1, path.display()))
+        })
+        .collect()
+}
@@ -0,0 +1,269 @@
+//! Temporary git repositories, a mock OpenAI-compatible server and a mock forge API, for running
+//! b4sam end to end without a network or an API key
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A git repository in a temporary directory, with its own home directory so that the user's
+/// config, cache and usage ledger are never touched. The directory is removed on drop.
+pub struct TestRepo {
+    root: PathBuf,
+    pub dir: PathBuf,
+}
+
+impl TestRepo {
+    /// An empty repository on `main`
+    pub fn new() -> Self {
+        let root = std::env::temp_dir().join(format!(
+            "b4sam-test-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        let dir = root.join("repo");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::create_dir_all(root.join("home")).unwrap();
+
+        let repo = TestRepo { root, dir };
+        repo.git(&["init", "-q", "-b", "main"]);
+        repo
+    }
+
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.dir.join(path)
+    }
+
+    /// A path in the temporary directory, outside the repository
+    pub fn scratch_path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    pub fn write(&self, path: &str, contents: &str) {
+        let path = self.path(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    /// Runs git in the repository, panicking if it fails
+    pub fn git(&self, args: &[&str]) -> String {
+        let output = self.command("git").args(args).output().unwrap();
+        assert!(
+            output.status.success(),
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    /// Runs a program in the repository with the test environment, e.g. git when it may fail
+    pub fn run(&self, program: &str, args: &[&str]) -> Output {
+        self.command(program).args(args).output().unwrap()
+    }
+
+    /// Commits every change in the working tree
+    pub fn commit(&self, message: &str) {
+        self.git(&["add", "-A"]);
+        self.git(&["commit", "-q", "-m", message]);
+    }
+
+    /// Runs b4sam in the repository
+    pub fn b4sam(&self, args: &[&str]) -> Output {
+        self.b4sam_with_env(args, &[])
+    }
+
+    /// Runs b4sam in a subdirectory of the repository
+    pub fn b4sam_in(&self, subdir: &str, args: &[&str]) -> Output {
+        self.command(env!("CARGO_BIN_EXE_b4sam"))
+            .current_dir(self.path(subdir))
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    pub fn b4sam_with_env(&self, args: &[&str], env: &[(&str, &Path)]) -> Output {
+        let mut command = self.command(env!("CARGO_BIN_EXE_b4sam"));
+        command.args(args);
+        for (key, value) in env {
+            command.env(key, value);
+        }
+        command.output().unwrap()
+    }
+
+    fn command(&self, program: &str) -> Command {
+        let home = self.root.join("home");
+        let mut command = Command::new(program);
+        command
+            .current_dir(&self.dir)
+            .env("HOME", &home)
+            .env("XDG_CONFIG_HOME", home.join(".config"))
+            .env("XDG_CACHE_HOME", home.join(".cache"))
+            .env("XDG_DATA_HOME", home.join(".local/share"))
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_AUTHOR_NAME", "Test")
+            .env("GIT_AUTHOR_EMAIL", "test@example.com")
+            .env("GIT_COMMITTER_NAME", "Test")
+            .env("GIT_COMMITTER_EMAIL", "test@example.com")
+            .env("OPENAI_API_KEY", "test")
+            .env_remove("B4SAM_RECORD")
+            .env_remove("B4SAM_REPLAY")
+            .env_remove("B4SAM_SKIP");
+        command
+    }
+}
+
+impl Drop for TestRepo {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// An OpenAI-compatible chat completions server that answers every request with the same JSON
+pub struct MockServer {
+    pub base_url: String,
+    requests: Arc<Mutex<Vec<serde_json::Value>>>,
+}
+
+impl MockServer {
+    /// Starts a server whose responses have `content` as the message, serialized as JSON
+    pub fn start(content: serde_json::Value) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let Some((request, stream)) = read_request(stream) else {
+                    continue;
+                };
+                received.lock().unwrap().push(request.body);
+                respond(stream, 200, &completion(&content));
+            }
+        });
+
+        MockServer { base_url, requests }
+    }
+
+    /// The bodies of the requests received so far
+    pub fn requests(&self) -> Vec<serde_json::Value> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// A request received by a [`MockForge`]
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    /// The path and query, as sent
+    pub path: String,
+    /// The JSON body, or `null` if there is none
+    pub body: serde_json::Value,
+}
+
+/// A stand-in for the GitHub or GitLab API, which answers each request with the status and JSON
+/// that `route` returns for it
+pub struct MockForge {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockForge {
+    pub fn start(route: impl Fn(&Request) -> (u16, serde_json::Value) + Send + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let Some((request, stream)) = read_request(stream) else {
+                    continue;
+                };
+                let (status, body) = route(&request);
+                received.lock().unwrap().push(request);
+                respond(stream, status, &body);
+            }
+        });
+
+        MockForge { url, requests }
+    }
+
+    /// The requests received so far, in order
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Reads one request. Servers record it before writing the response, so that it is there by
+/// the time b4sam exits.
+fn read_request(stream: TcpStream) -> Option<(Request, TcpStream)> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().ok()?;
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).ok()?;
+    let body = if body.is_empty() {
+        serde_json::Value::Null
+    } else {
+        serde_json::from_slice(&body).ok()?
+    };
+
+    Some((Request { method, path, body }, reader.into_inner()))
+}
+
+/// A chat completion with `content` as the message
+fn completion(content: &serde_json::Value) -> serde_json::Value {
+    serde_json::json!({
+        "id": "chatcmpl-test",
+        "object": "chat.completion",
+        "created": 0,
+        "model": "test",
+        "system_fingerprint": null,
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": content.to_string() },
+            "logprobs": null,
+            "finish_reason": "stop",
+        }],
+        "usage": { "prompt_tokens": 100, "completion_tokens": 20, "total_tokens": 120 },
+    })
+}
+
+fn respond(mut stream: TcpStream, status: u16, body: &serde_json::Value) -> Option<()> {
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+    .ok()
+}
@@ -0,0 +1,578 @@
+mod common;
+
+use common::{MockForge, MockServer, TestRepo};
+use serde_json::json;
+
+/// A repository whose `feature` branch adds a debug print to `src/lib.rs`
+fn feature_repo() -> TestRepo {
+    let repo = TestRepo::new();
+    repo.write(
+        "src/lib.rs",
+        "fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n",
+    );
+    repo.commit("Add add");
+    repo.git(&["checkout", "-q", "-b", "feature"]);
+    repo.write(
+        "src/lib.rs",
+        "fn add(a: i32, b: i32) -> i32 {\n    println!(\"adding\");\n    a + b\n}\n",
+    );
+    repo.commit("Log additions");
+    repo
+}
+
+fn debug_print_review() -> serde_json::Value {
+    json!({
+        "comments": [{
+            "comment_type": "LeftoverDebug",
+            "path": "src/lib.rs",
+            "start_line": 2,
+            "end_line": 2,
+            "side": "New",
+            "line": "    println!(\"adding\");",
+            "comment": "This print looks like it was left in by mistake",
+            "fix": null,
+        }]
+    })
+}
+
+/// A comment on the debug print, one on the first three lines of `add` and one on a file that
+/// isn't in the diff
+fn forge_review() -> serde_json::Value {
+    json!({
+        "comments": [
+            debug_print_review()["comments"][0],
+            {
+                "comment_type": "Suggestion",
+                "path": "src/lib.rs",
+                "start_line": 1,
+                "end_line": 3,
+                "side": "New",
+                "line": "fn add(a: i32, b: i32) -> i32 {",
+                "comment": "Document the logging",
+                "fix": null,
+            },
+            {
+                "comment_type": "Question",
+                "path": "src/other.rs",
+                "start_line": 5,
+                "end_line": 5,
+                "side": "New",
+                "line": "other()",
+                "comment": "Is this still needed?",
+                "fix": null,
+            },
+        ]
+    })
+}
+
+fn stdout(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn show_diff_shows_committed_changes() {
+    let repo = feature_repo();
+
+    let output = repo.b4sam(&["show-diff", "--against", "main"]);
+
+    assert!(output.status.success());
+    let diff = stdout(&output);
+    assert!(diff.contains("diff --git a/src/lib.rs b/src/lib.rs"));
+    assert!(diff.contains("+    println!(\"adding\");"));
+}
+
+#[test]
+fn show_diff_includes_untracked_files_in_the_worktree() {
+    let repo = feature_repo();
+    repo.write("src/new.rs", "pub fn new() {}\n");
+
+    let committed = stdout(&repo.b4sam(&["show-diff", "--against", "main"]));
+    let worktree = stdout(&repo.b4sam(&["show-diff", "--worktree"]));
+
+    assert!(!committed.contains("src/new.rs"));
+    assert!(worktree.contains("+++ b/src/new.rs"));
+    assert!(worktree.contains("+pub fn new() {}"));
+}
+
+#[test]
+fn untracked_files_have_paths_from_the_top_level_in_subdirectories() {
+    let repo = feature_repo();
+    repo.write(
+        "src/lib.rs",
+        "fn add(a: i32, b: i32) -> i32 {\n    a - b\n}\n",
+    );
+    repo.write("src/new.rs", "pub fn new() {}\n");
+
+    let output = repo.b4sam_in("src", &["show-diff", "--unstaged"]);
+
+    assert!(output.status.success());
+    let diff = stdout(&output);
+    assert!(diff.contains("diff --git a/src/lib.rs b/src/lib.rs"));
+    assert!(diff.contains("diff --git a/src/new.rs b/src/new.rs"));
+    assert!(diff.contains("+++ b/src/new.rs"));
+}
+
+#[test]
+fn review_prints_the_models_comments() {
+    let repo = feature_repo();
+    let server = MockServer::start(debug_print_review());
+
+    let output = repo.b4sam(&[
+        "review",
+        "--against",
+        "main",
+        "--base-url",
+        &server.base_url,
+    ]);
+
+    assert!(output.status.success(), "{:?}", output);
+    let text = stdout(&output);
+    assert!(text.contains("[LeftoverDebug]"));
+    assert!(text.contains("in: src/lib.rs:2"));
+    assert!(text.contains("This print looks like it was left in by mistake"));
+    assert!(!text.contains("not found in the diff"));
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    let prompt = requests[0]["messages"][1]["content"].to_string();
+    assert!(prompt.contains("println!"));
+}
+
+#[test]
+fn review_json_includes_metadata_and_anchored_comments() {
+    let repo = feature_repo();
+    let server = MockServer::start(debug_print_review());
+
+    let output = repo.b4sam(&[
+        "review",
+        "--against",
+        "main",
+        "--format",
+        "json",
+        "--base-url",
+        &server.base_url,
+        "--model",
+        "gpt-4o",
+    ]);
+
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["model"], "gpt-4o");
+    assert_eq!(report["comments"][0]["comment_type"], "LeftoverDebug");
+    assert_eq!(report["comments"][0]["anchored"], true);
+}
+
+#[test]
+fn fail_on_exits_with_status_one_for_findings() {
+    let repo = feature_repo();
+    let server = MockServer::start(debug_print_review());
+    let review = |fail_on: &str| {
+        repo.b4sam(&[
+            "review",
+            "--against",
+            "main",
+            "--base-url",
+            &server.base_url,
+            "--no-cache",
+            "--fail-on",
+            fail_on,
+        ])
+        .status
+        .code()
+    };
+
+    assert_eq!(review("error"), Some(1));
+    assert_eq!(review("info"), Some(1));
+
+    let nitpicks = MockServer::start(json!({
+        "comments": [{
+            "comment_type": "Nitpick",
+            "path": "src/lib.rs",
+            "start_line": 1,
+            "end_line": 1,
+            "side": "New",
+            "line": "fn add(a: i32, b: i32) -> i32 {",
+            "comment": "Consider a doc comment",
+            "fix": null,
+        }]
+    }));
+    let output = repo.b4sam(&[
+        "review",
+        "--against",
+        "main",
+        "--base-url",
+        &nitpicks.base_url,
+        "--no-cache",
+        "--fail-on",
+        "error",
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn ignore_directives_drop_comments() {
+    let repo = feature_repo();
+    repo.write(
+        "src/lib.rs",
+        "fn add(a: i32, b: i32) -> i32 {\n    println!(\"adding\"); // b4sam:ignore LeftoverDebug\n    a + b\n}\n",
+    );
+    repo.commit("Keep the print");
+    let server = MockServer::start(json!({
+        "comments": [{
+            "comment_type": "LeftoverDebug",
+            "path": "src/lib.rs",
+            "start_line": 2,
+            "end_line": 2,
+            "side": "New",
+            "line": "    println!(\"adding\"); // b4sam:ignore LeftoverDebug",
+            "comment": "Debug print",
+            "fix": null,
+        }]
+    }));
+
+    let output = repo.b4sam(&[
+        "review",
+        "--against",
+        "main",
+        "--base-url",
+        &server.base_url,
+    ]);
+
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!stdout(&output).contains("Debug print"));
+    let system_prompt = server.requests()[0]["messages"][0]["content"].to_string();
+    assert!(system_prompt.contains("src/lib.rs:2 (LeftoverDebug only)"));
+}
+
+#[test]
+fn recorded_responses_are_replayed_without_the_server() {
+    let repo = feature_repo();
+    let fixtures = repo.scratch_path("fixtures");
+    let server = MockServer::start(debug_print_review());
+
+    let recorded = repo.b4sam_with_env(
+        &[
+            "review",
+            "--against",
+            "main",
+            "--base-url",
+            &server.base_url,
+            "--no-cache",
+        ],
+        &[("B4SAM_RECORD", &fixtures)],
+    );
+    assert!(recorded.status.success(), "{:?}", recorded);
+    assert_eq!(std::fs::read_dir(&fixtures).unwrap().count(), 1);
+
+    // nothing listens on port 9 (discard), so any request would fail
+    let replayed = repo.b4sam_with_env(
+        &[
+            "review",
+            "--against",
+            "main",
+            "--base-url",
+            "http://127.0.0.1:9/v1",
+            "--no-cache",
+        ],
+        &[("B4SAM_REPLAY", &fixtures)],
+    );
+    assert!(replayed.status.success(), "{:?}", replayed);
+    assert!(stdout(&replayed).contains("This print looks like it was left in by mistake"));
+
+    repo.write(
+        "src/lib.rs",
+        "fn add(a: i32, b: i32) -> i32 {\n    b + a\n}\n",
+    );
+    repo.commit("Swap the operands");
+    let missing = repo.b4sam_with_env(
+        &["review", "--against", "main", "--no-cache"],
+        &[("B4SAM_REPLAY", &fixtures)],
+    );
+    assert_eq!(missing.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("No recorded response"));
+}
+
+#[test]
+fn changed_files_are_sent_in_full_for_context() {
+    let repo = feature_repo();
+    let server = MockServer::start(debug_print_review());
+    let review = |extra: &[&str]| {
+        let mut args = vec![
+            "review",
+            "--against",
+            "main",
+            "--base-url",
+            &server.base_url,
+            "--no-cache",
+            "-U",
+            "0",
+        ];
+        args.extend(extra);
+        assert!(repo.b4sam(&args).status.success());
+        server.requests().last().unwrap()["messages"][1]["content"][0]["text"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    };
+
+    let prompt = review(&[]);
+    assert!(prompt.starts_with("<context>\n<file path=\"src/lib.rs\" reason=\"changed\">\nfn add(a: i32, b: i32) -> i32 {\n"));
+    assert!(prompt.contains("</context>\n\n<diff>\ndiff --git a/src/lib.rs b/src/lib.rs"));
+
+    let prompt = review(&["--context-tokens", "0"]);
+    assert!(prompt.starts_with("diff --git"));
+}
+
+#[test]
+fn related_files_add_the_definitions_of_called_functions() {
+    let repo = TestRepo::new();
+    repo.write(
+        "src/math.rs",
+        "pub fn double(x: i32) -> i32 {\n    x * 2\n}\n",
+    );
+    repo.write("src/other.rs", "pub fn unrelated() {}\n");
+    repo.write(
+        "src/lib.rs",
+        "fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n",
+    );
+    repo.commit("Add math");
+    repo.git(&["checkout", "-q", "-b", "feature"]);
+    repo.write(
+        "src/lib.rs",
+        "fn add(a: i32, b: i32) -> i32 {\n    double(a) + b\n}\n",
+    );
+    repo.commit("Double the first operand");
+    let server = MockServer::start(json!({ "comments": [] }));
+
+    let output = repo.b4sam(&[
+        "review",
+        "--against",
+        "main",
+        "--base-url",
+        &server.base_url,
+        "--related-files",
+    ]);
+
+    assert!(output.status.success(), "{:?}", output);
+    let prompt = server.requests()[0]["messages"][1]["content"].to_string();
+    assert!(prompt.contains("<file path=\\\"src/math.rs\\\" reason=\\\"related\\\">"));
+    assert!(!prompt.contains("src/other.rs"));
+}
+
+#[test]
+fn github_post_posts_one_review_with_inline_comments() {
+    let repo = feature_repo();
+    let server = MockServer::start(forge_review());
+    let github =
+        MockForge::start(|_| (200, json!({ "html_url": "https://github.test/o/r/pull/7" })));
+
+    let output = repo.b4sam(&[
+        "github-post",
+        "--against",
+        "main",
+        "--base-url",
+        &server.base_url,
+        "--api-url",
+        &github.url,
+        "--token",
+        "secret",
+        "--repo",
+        "o/r",
+        "--pr",
+        "7",
+    ]);
+
+    assert!(output.status.success(), "{:?}", output);
+    assert!(stdout(&output).contains("Posted review on o/r#7: https://github.test/o/r/pull/7"));
+    let requests = github.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, "/repos/o/r/pulls/7/reviews");
+
+    let review = &requests[0].body;
+    assert_eq!(review["commit_id"], repo.git(&["rev-parse", "HEAD"]));
+    assert_eq!(review["event"], "COMMENT");
+    assert_eq!(
+        review["comments"],
+        json!([
+            {
+                "path": "src/lib.rs",
+                "line": 2,
+                "side": "RIGHT",
+                "body": "**LeftoverDebug**: This print looks like it was left in by mistake",
+            },
+            {
+                "path": "src/lib.rs",
+                "start_line": 1,
+                "start_side": "RIGHT",
+                "line": 3,
+                "side": "RIGHT",
+                "body": "**Suggestion**: Document the logging",
+            },
+        ])
+    );
+    // the comment that isn't on the diff is listed in the body instead
+    let body = review["body"].as_str().unwrap();
+    assert!(body.starts_with("b4sam left 3 comment(s)"));
+    assert!(body.contains("- `src/other.rs:5` **Question**: Is this still needed?"));
+    assert!(!body.contains("Document the logging"));
+}
+
+#[test]
+fn gitlab_post_starts_discussions_and_leaves_a_summary() {
+    let repo = feature_repo();
+    let server = MockServer::start(forge_review());
+    // GitLab rejects the discussion on the added line
+    let gitlab = MockForge::start(|request| {
+        if request.body["position"]["new_line"] == 2 {
+            (
+                400,
+                json!({ "message": "400 Bad request - Note {:line_code=>[\"can't be blank\"]}" }),
+            )
+        } else {
+            (201, json!({ "id": 1 }))
+        }
+    });
+
+    let output = repo.b4sam(&[
+        "gitlab-post",
+        "--against",
+        "main",
+        "--base-url",
+        &server.base_url,
+        "--url",
+        &gitlab.url,
+        "--token",
+        "secret",
+        "--project",
+        "group/project",
+        "--mr",
+        "3",
+    ]);
+
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Could not start a discussion on src/lib.rs:2"));
+    let requests = gitlab.requests();
+    let paths: Vec<&str> = requests
+        .iter()
+        .map(|request| request.path.as_str())
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            "/api/v4/projects/group%2Fproject/merge_requests/3/discussions",
+            "/api/v4/projects/group%2Fproject/merge_requests/3/discussions",
+            "/api/v4/projects/group%2Fproject/merge_requests/3/notes",
+        ]
+    );
+
+    let base = repo.git(&["rev-parse", "main"]);
+    let head = repo.git(&["rev-parse", "HEAD"]);
+    let position = |old_line: Option<u32>, new_line: u32| {
+        let mut position = json!({
+            "position_type": "text",
+            "base_sha": base,
+            "start_sha": base,
+            "head_sha": head,
+            "old_path": "src/lib.rs",
+            "new_path": "src/lib.rs",
+            "new_line": new_line,
+        });
+        if let Some(old_line) = old_line {
+            position["old_line"] = json!(old_line);
+        }
+        position
+    };
+    // added lines only have a new line number, unchanged lines have both
+    assert_eq!(requests[0].body["position"], position(None, 2));
+    assert_eq!(requests[1].body["position"], position(Some(1), 1));
+    assert_eq!(
+        requests[1].body["body"],
+        "**Suggestion**: Document the logging"
+    );
+
+    // the rejected comment is in the summary, along with the one that isn't on the diff
+    let note = requests[2].body["body"].as_str().unwrap();
+    assert!(note.starts_with("b4sam left 3 comment(s)"));
+    assert!(note.contains("- `src/lib.rs:2` **LeftoverDebug**"));
+    assert!(note.contains("- `src/other.rs:5` **Question**"));
+    assert!(!note.contains("Document the logging"));
+}
+
+#[cfg(unix)]
+#[test]
+fn hooks_chain_to_the_existing_hook_and_tell_findings_from_failures() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let repo = feature_repo();
+    let server = MockServer::start(debug_print_review());
+    // a relative hooks path is relative to the top level, wherever b4sam is run from
+    repo.git(&["config", "core.hooksPath", "hooks"]);
+    let ran = repo.scratch_path("chained-hook-ran");
+    repo.write(
+        "hooks/pre-commit",
+        &format!("#!/bin/sh\necho ran >> '{}'\n", ran.display()),
+    );
+    std::fs::set_permissions(
+        repo.path("hooks/pre-commit"),
+        std::fs::Permissions::from_mode(0o755),
+    )
+    .unwrap();
+
+    let output = repo.b4sam_in(
+        "src",
+        &[
+            "hook",
+            "install",
+            "--hook",
+            "pre-commit",
+            "--mode",
+            "advise",
+        ],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let installed = std::fs::read_to_string(repo.path("hooks/pre-commit")).unwrap();
+    assert!(installed.contains("# b4sam-hook"));
+    assert!(
+        std::fs::read_to_string(repo.path("hooks/pre-commit.pre-b4sam"))
+            .unwrap()
+            .contains("echo ran")
+    );
+    assert!(!repo.path("src/hooks").exists());
+    let status = stdout(&repo.b4sam(&["hook", "status"]));
+    assert!(status.contains("pre-commit: installed (advise), chained to the previous hook"));
+
+    // findings (status 1) are shown without being reported as a failure
+    repo.write(
+        ".b4sam.toml",
+        &format!("base_url = \"{}\"\nfail_on = \"info\"\n", server.base_url),
+    );
+    repo.write(
+        "src/lib.rs",
+        "fn add(a: i32, b: i32) -> i32 {\n    b + a\n}\n",
+    );
+    repo.git(&["add", "-A"]);
+    let commit = repo.run("git", &["commit", "-q", "-m", "Swap the operands"]);
+    assert!(commit.status.success(), "{:?}", commit);
+    // git sends the output of hooks to stderr
+    let stderr = String::from_utf8_lossy(&commit.stderr);
+    assert!(stderr.contains("This print looks like it was left in by mistake"));
+    assert!(!stderr.contains("the review failed"));
+    assert_eq!(server.requests().len(), 1);
+
+    // errors (status 2) are, but still don't stop the commit
+    repo.write(".b4sam.toml", "base_url = \"http://127.0.0.1:9/v1\"\n");
+    repo.git(&["add", "-A"]);
+    let commit = repo.run("git", &["commit", "-q", "-m", "Break the config"]);
+    assert!(commit.status.success(), "{:?}", commit);
+    assert!(String::from_utf8_lossy(&commit.stderr)
+        .contains("b4sam: the review failed, continuing with the commit"));
+    assert_eq!(std::fs::read_to_string(&ran).unwrap(), "ran\nran\n");
+
+    let output = repo.b4sam(&["hook", "uninstall", "--hook", "pre-commit"]);
+    assert!(output.status.success(), "{:?}", output);
+    let restored = std::fs::read_to_string(repo.path("hooks/pre-commit")).unwrap();
+    assert!(restored.contains("echo ran") && !restored.contains("# b4sam-hook"));
+    assert!(!repo.path("hooks/pre-commit.pre-b4sam").exists());
+}